@@ -1,15 +1,21 @@
-use crate::{AmaterasuConfig, WipeMode};
+use crate::storage::detector::{normalize_wwn, BlockDevice};
+use crate::storage::StorageType;
+use crate::{AmaterasuConfig, SharedExtentPolicy, WipeMode};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ConfigFile {
     #[serde(default)]
     pub defaults: DefaultConfig,
     #[serde(default)]
     pub modes: ModeConfigs,
+    /// Per-device overrides keyed by a user-chosen label
+    #[serde(default)]
+    pub devices: BTreeMap<String, DeviceOverride>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +26,9 @@ pub struct DefaultConfig {
     pub verify: bool,
     #[serde(default = "default_true")]
     pub progress: bool,
+    /// `warn` or `refuse` when a file shares extents with other files
+    #[serde(default)]
+    pub shared_extents: SharedExtentPolicy,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,12 +55,64 @@ pub struct ModeConfig {
     pub disable_cow: bool,
 }
 
+/// Overrides the detected storage type and pass count for one device.
+/// A device matches when any of `serial`, `wwn` or `path` matches it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceOverride {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wwn: Option<String>,
+    /// Device node such as `/dev/sdb` or `/dev/disk/by-id/usb-...`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// One of `hdd`, `ssd`, `nvme` or `removable`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passes: Option<usize>,
+}
+
+impl DeviceOverride {
+    /// Whether this entry names `device` or any device it is stacked on
+    pub fn matches(&self, device: &BlockDevice) -> bool {
+        device
+            .layers()
+            .into_iter()
+            .any(|layer| self.matches_layer(layer))
+    }
+
+    fn matches_layer(&self, device: &BlockDevice) -> bool {
+        let serial_matches = match (&self.serial, &device.serial) {
+            (Some(wanted), Some(actual)) => wanted.trim() == actual.trim(),
+            _ => false,
+        };
+
+        let wwn_matches = match (&self.wwn, &device.wwn) {
+            (Some(wanted), Some(actual)) => normalize_wwn(wanted) == normalize_wwn(actual),
+            _ => false,
+        };
+
+        let path_matches = self.path.as_ref().is_some_and(|path| {
+            // Resolve /dev/disk/by-* symlinks down to the kernel name
+            let resolved = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+            resolved.file_name().is_some_and(|name| {
+                name.to_string_lossy() == device.name
+                    || device.partition.as_deref() == Some(&*name.to_string_lossy())
+            })
+        });
+
+        serial_matches || wwn_matches || path_matches
+    }
+}
+
 impl Default for DefaultConfig {
     fn default() -> Self {
         Self {
             mode: default_mode(),
             verify: default_true(),
             progress: default_true(),
+            shared_extents: SharedExtentPolicy::default(),
         }
     }
 }
@@ -108,15 +169,6 @@ impl Default for ModeConfigs {
     }
 }
 
-impl Default for ConfigFile {
-    fn default() -> Self {
-        Self {
-            defaults: DefaultConfig::default(),
-            modes: ModeConfigs::default(),
-        }
-    }
-}
-
 fn default_mode() -> String {
     "standard".to_string()
 }
@@ -184,7 +236,7 @@ pub fn create_default_config(path: &Path) -> Result<()> {
 }
 
 pub fn apply_config_to_amaterasu(
-    _config: &ConfigFile,
+    config: &ConfigFile,
     cli_config: AmaterasuConfig,
 ) -> AmaterasuConfig {
     // CLI arguments take precedence over config file
@@ -195,6 +247,21 @@ pub fn apply_config_to_amaterasu(
         mode: cli_config.mode,
         wipe_metadata: cli_config.wipe_metadata,
         metadata_passes: cli_config.metadata_passes,
+        // Device policies only exist in the config file
+        device_policies: config.devices.clone(),
+        // --refuse-shared can only tighten the configured policy
+        shared_extents: match cli_config.shared_extents {
+            SharedExtentPolicy::Refuse => SharedExtentPolicy::Refuse,
+            SharedExtentPolicy::Warn => config.defaults.shared_extents,
+        },
+        snapshot_report: cli_config.snapshot_report,
+        allow_remote: cli_config.allow_remote,
+        encryption_report: cli_config.encryption_report,
+        fixed_timestamp: cli_config.fixed_timestamp,
+        follow_symlinks: cli_config.follow_symlinks,
+        hardlinks: cli_config.hardlinks,
+        special_files: cli_config.special_files,
+        clear_immutable: cli_config.clear_immutable,
     }
 }
 
@@ -207,6 +274,45 @@ pub fn parse_mode_from_config(mode_str: &str) -> WipeMode {
     }
 }
 
+/// Build the storage type a `[devices]` entry asks for, keeping whatever
+/// the detector learned about the device where the override is silent.
+pub fn parse_storage_type_from_config(
+    storage_str: &str,
+    detected: &StorageType,
+) -> Option<StorageType> {
+    use crate::storage::Transport;
+
+    let trim_support = match detected {
+        StorageType::SSD { trim_support } | StorageType::Removable { trim_support, .. } => {
+            *trim_support
+        }
+        StorageType::NVMe { .. } => true,
+        _ => false,
+    };
+
+    match storage_str.to_lowercase().as_str() {
+        "hdd" => Some(StorageType::HDD {
+            rotational: true,
+            block_size: match detected {
+                StorageType::HDD { block_size, .. } => *block_size,
+                _ => 512,
+            },
+        }),
+        "ssd" => Some(StorageType::SSD { trim_support }),
+        "nvme" => Some(StorageType::NVMe {
+            optimal_io_size: detected.get_optimal_block_size(),
+        }),
+        "removable" => Some(StorageType::Removable {
+            transport: match detected {
+                StorageType::Removable { transport, .. } => *transport,
+                _ => Transport::Unknown,
+            },
+            trim_support,
+        }),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -225,6 +331,30 @@ mod tests {
         assert_eq!(config.defaults.mode, "standard");
         assert!(config.defaults.verify);
         assert!(config.defaults.progress);
+        assert_eq!(config.defaults.shared_extents, SharedExtentPolicy::Warn);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_extent_policy() -> Result<()> {
+        let config: ConfigFile = toml::from_str(
+            r#"
+            [defaults]
+            shared_extents = "refuse"
+            "#,
+        )?;
+        assert_eq!(config.defaults.shared_extents, SharedExtentPolicy::Refuse);
+        assert!(toml::from_str::<ConfigFile>("[defaults]\nshared_extents = \"maybe\"").is_err());
+
+        let applied = apply_config_to_amaterasu(&config, AmaterasuConfig::default());
+        assert_eq!(applied.shared_extents, SharedExtentPolicy::Refuse);
+        let cli = AmaterasuConfig {
+            shared_extents: SharedExtentPolicy::Refuse,
+            ..AmaterasuConfig::default()
+        };
+        let applied = apply_config_to_amaterasu(&ConfigFile::default(), cli);
+        assert_eq!(applied.shared_extents, SharedExtentPolicy::Refuse);
 
         Ok(())
     }
@@ -250,4 +380,68 @@ mod tests {
             WipeMode::Standard
         ));
     }
+
+    fn usb_stick() -> BlockDevice {
+        BlockDevice {
+            name: "sdb".to_string(),
+            partition: Some("sdb1".to_string()),
+            kind: crate::storage::detector::DeviceKind::Disk,
+            serial: Some("4C530001230918110463".to_string()),
+            wwn: Some("naa.5000000000000001".to_string()),
+            removable: true,
+            transport: crate::storage::Transport::Usb,
+            storage_type: StorageType::HDD {
+                rotational: true,
+                block_size: 512,
+            },
+            underlying: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_device_overrides() -> Result<()> {
+        let config: ConfigFile = toml::from_str(
+            r#"
+            [devices.stick]
+            serial = "4C530001230918110463"
+            storage = "removable"
+            passes = 1
+
+            [devices.by-wwn]
+            wwn = "0x5000000000000001"
+
+            [devices.by-path]
+            path = "/dev/sdb1"
+
+            [devices.other]
+            serial = "SOMETHING-ELSE"
+            "#,
+        )?;
+
+        let device = usb_stick();
+        assert!(config.devices["stick"].matches(&device));
+        assert!(config.devices["by-wwn"].matches(&device));
+        assert!(config.devices["by-path"].matches(&device));
+        assert!(!config.devices["other"].matches(&device));
+        assert_eq!(config.devices["stick"].passes, Some(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_storage_type_parsing() {
+        let detected = usb_stick().storage_type;
+        assert!(matches!(
+            parse_storage_type_from_config("Removable", &detected),
+            Some(StorageType::Removable { .. })
+        ));
+        assert!(matches!(
+            parse_storage_type_from_config("hdd", &detected),
+            Some(StorageType::HDD {
+                block_size: 512,
+                ..
+            })
+        ));
+        assert!(parse_storage_type_from_config("tape", &detected).is_none());
+    }
 }
@@ -0,0 +1,165 @@
+use super::flags::{self, FS_COMPR_FL, FS_NOCOMP_FL, FS_NOCOW_FL};
+use super::{FilesystemInfo, FilesystemType};
+use std::ffi::CString;
+use std::fmt;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+
+/// Why a file's data may be stored compressed or deduplicated. On such
+/// files a block of zeros or 0x55 is written as a few bytes in a new
+/// extent and never covers the original blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressionSource {
+    /// btrfs `compress=`/`compress-force=` mount option
+    MountOption(String),
+    /// `chattr +c` on the file
+    InodeFlag,
+    /// btrfs `compression` property set on the file
+    Property(String),
+    ZfsCompression,
+    ZfsDedup,
+}
+
+impl fmt::Display for CompressionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressionSource::MountOption(algorithm) => {
+                write!(f, "mount option compress={}", algorithm)
+            }
+            CompressionSource::InodeFlag => write!(f, "compression flag (chattr +c)"),
+            CompressionSource::Property(algorithm) => {
+                write!(f, "btrfs compression property ({})", algorithm)
+            }
+            CompressionSource::ZfsCompression => write!(f, "ZFS dataset compression"),
+            CompressionSource::ZfsDedup => write!(f, "ZFS dataset deduplication"),
+        }
+    }
+}
+
+/// Everything that makes `file` compressed or deduplicated on `filesystem`
+pub fn detect_compression(file: &File, filesystem: &FilesystemInfo) -> Vec<CompressionSource> {
+    match &filesystem.fs_type {
+        FilesystemType::Btrfs {
+            compression,
+            nodatacow,
+            ..
+        } => {
+            let file_flags = flags::get_flags(file).unwrap_or(0);
+
+            // NOCOW data is never compressed, whatever the mount says
+            if *nodatacow || file_flags & FS_NOCOW_FL != 0 {
+                return Vec::new();
+            }
+
+            let mut sources = Vec::new();
+            if let Some(algorithm) = compression {
+                // compress-force ignores the per-file opt-out
+                if file_flags & FS_NOCOMP_FL == 0 || filesystem.options.compress_force {
+                    sources.push(CompressionSource::MountOption(algorithm.clone()));
+                }
+            }
+            if file_flags & FS_COMPR_FL != 0 {
+                sources.push(CompressionSource::InodeFlag);
+            }
+            if let Some(algorithm) = read_xattr(file, "btrfs.compression") {
+                sources.push(CompressionSource::Property(algorithm));
+            }
+            sources
+        }
+        FilesystemType::Zfs { compression, dedup } => {
+            let mut sources = Vec::new();
+            if *compression {
+                sources.push(CompressionSource::ZfsCompression);
+            }
+            if *dedup {
+                sources.push(CompressionSource::ZfsDedup);
+            }
+            sources
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn read_xattr(file: &File, name: &str) -> Option<String> {
+    let name = CString::new(name).ok()?;
+    let mut value = [0u8; 64];
+
+    let len = unsafe {
+        libc::fgetxattr(
+            file.as_raw_fd(),
+            name.as_ptr(),
+            value.as_mut_ptr().cast(),
+            value.len(),
+        )
+    };
+    if len <= 0 {
+        return None;
+    }
+
+    let value = String::from_utf8_lossy(&value[..len as usize]);
+    let value = value.trim_end_matches('\0').trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::mountinfo::MountOptions;
+    use tempfile::NamedTempFile;
+
+    fn filesystem(fs_type: FilesystemType, options: MountOptions) -> FilesystemInfo {
+        FilesystemInfo {
+            fs_type,
+            mount: None,
+            options,
+        }
+    }
+
+    #[test]
+    fn test_btrfs_mount_compression() {
+        let file = NamedTempFile::new().unwrap();
+        let btrfs = filesystem(
+            FilesystemType::Btrfs {
+                subvolume: false,
+                compression: Some("zstd:3".to_string()),
+                nodatacow: false,
+            },
+            MountOptions::default(),
+        );
+
+        assert_eq!(
+            detect_compression(file.as_file(), &btrfs),
+            [CompressionSource::MountOption("zstd:3".to_string())]
+        );
+
+        let nodatacow = filesystem(
+            FilesystemType::Btrfs {
+                subvolume: false,
+                compression: Some("zstd:3".to_string()),
+                nodatacow: true,
+            },
+            MountOptions::default(),
+        );
+        assert!(detect_compression(file.as_file(), &nodatacow).is_empty());
+    }
+
+    #[test]
+    fn test_zfs_compression_and_dedup() {
+        let file = NamedTempFile::new().unwrap();
+        let zfs = filesystem(
+            FilesystemType::Zfs {
+                compression: true,
+                dedup: true,
+            },
+            MountOptions::default(),
+        );
+
+        assert_eq!(
+            detect_compression(file.as_file(), &zfs),
+            [
+                CompressionSource::ZfsCompression,
+                CompressionSource::ZfsDedup
+            ]
+        );
+    }
+}
@@ -1,48 +1,97 @@
-use super::FilesystemOptimizer;
+use super::flags::{self, FS_NOCOW_FL};
+use super::mountinfo::MountOptions;
+use super::{ensure_writable, FilesystemOptimizer};
 use crate::Result;
+use std::fs::File;
 use std::path::Path;
-use std::process::Command;
 
-pub struct BtrfsOptimizer;
+/// Whether overwrites of a file land on the blocks that held its data
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum CowStatus {
+    /// The filesystem overwrites in place
+    #[default]
+    NotApplicable,
+    /// btrfs mounted with `nodatacow`
+    NodatacowMount,
+    /// The file already had the NOCOW flag
+    AlreadyDisabled,
+    /// NOCOW was set and read back
+    Disabled,
+    /// The flag was accepted but did not stick (btrfs, file has data)
+    Ignored,
+    Failed(String),
+    /// Copy-on-write cannot be turned off (ZFS)
+    Unavoidable,
+}
+
+impl CowStatus {
+    pub fn overwrites_in_place(&self) -> bool {
+        matches!(
+            self,
+            CowStatus::NotApplicable
+                | CowStatus::NodatacowMount
+                | CowStatus::AlreadyDisabled
+                | CowStatus::Disabled
+        )
+    }
+}
+
+/// Set FS_NOCOW_FL on `file` and read the flags back to see if it took
+pub fn disable_btrfs_cow(file: &File) -> CowStatus {
+    let current = match flags::get_flags(file) {
+        Ok(current) => current,
+        Err(e) => return CowStatus::Failed(format!("{:#}", e)),
+    };
+    if current & FS_NOCOW_FL != 0 {
+        return CowStatus::AlreadyDisabled;
+    }
+
+    if let Err(e) = flags::set_flags(file, current | FS_NOCOW_FL) {
+        return CowStatus::Failed(format!("{:#}", e));
+    }
+
+    match flags::get_flags(file) {
+        Ok(after) if after & FS_NOCOW_FL != 0 => CowStatus::Disabled,
+        Ok(_) => CowStatus::Ignored,
+        Err(e) => CowStatus::Failed(format!("{:#}", e)),
+    }
+}
+
+pub struct BtrfsOptimizer {
+    pub options: MountOptions,
+}
 
 impl FilesystemOptimizer for BtrfsOptimizer {
     fn pre_wipe_setup(&self, path: &Path) -> Result<()> {
-        // Try to disable CoW for better security
-        println!(
-            "⚠️  Btrfs detected - attempting to disable CoW for file: {}",
-            path.display()
-        );
+        ensure_writable(&self.options, path)
+    }
 
-        // Use chattr +C to disable CoW
-        let output = Command::new("chattr").arg("+C").arg(path).output();
+    fn disable_cow(&self, file: &File) -> CowStatus {
+        if self.options.nodatacow {
+            println!("📁 Btrfs mounted with nodatacow: data is overwritten in place");
+            return CowStatus::NodatacowMount;
+        }
 
-        match output {
-            Ok(result) if result.status.success() => {
-                println!("✅ CoW disabled for file");
+        let status = disable_btrfs_cow(file);
+        match &status {
+            CowStatus::AlreadyDisabled => {
+                println!("📁 File is already NOCOW: data is overwritten in place")
             }
-            Ok(result) => {
-                println!(
-                    "⚠️  Failed to disable CoW: {}",
-                    String::from_utf8_lossy(&result.stderr)
-                );
-                println!("   Note: Wipe may be less effective on CoW filesystems");
+            CowStatus::Disabled => println!("✅ CoW disabled for file"),
+            CowStatus::Ignored => {
+                println!("⚠️  Btrfs ignored NOCOW: it only applies to files without data");
+                println!("   Note: Overwrites go to new extents, the old blocks are only freed");
             }
-            Err(e) => {
-                println!("⚠️  Could not run chattr command: {}", e);
-                println!("   Note: Install e2fsprogs for better Btrfs support");
+            CowStatus::Failed(reason) => {
+                println!("⚠️  Failed to disable CoW: {}", reason);
+                println!("   Note: Overwrites go to new extents, the old blocks are only freed");
             }
+            _ => {}
         }
-
-        Ok(())
+        status
     }
 
-    fn post_wipe_cleanup(&self, _path: &Path) -> Result<()> {
-        // Force defragmentation to ensure data is actually overwritten
-        println!("🔄 Attempting filesystem sync for CoW cleanup...");
-
-        // Use sync to ensure all data is written
-        let _ = Command::new("sync").status();
-
+    fn post_wipe_cleanup(&self, _file: &File) -> Result<()> {
         Ok(())
     }
 
@@ -52,26 +101,31 @@ impl FilesystemOptimizer for BtrfsOptimizer {
     }
 
     fn should_disable_cow(&self) -> bool {
-        true
+        !self.options.nodatacow
     }
 }
 
-pub struct ZfsOptimizer;
+pub struct ZfsOptimizer {
+    pub options: MountOptions,
+}
 
 impl FilesystemOptimizer for ZfsOptimizer {
     fn pre_wipe_setup(&self, path: &Path) -> Result<()> {
+        ensure_writable(&self.options, path)?;
+
         println!(
             "⚠️  ZFS detected - CoW filesystem limitations apply: {}",
             path.display()
         );
-        println!("   Note: ZFS snapshots may preserve deleted data");
-        println!("   Recommendation: Remove relevant snapshots after wiping");
         Ok(())
     }
 
-    fn post_wipe_cleanup(&self, _path: &Path) -> Result<()> {
-        println!("🔄 Forcing ZFS sync...");
-        let _ = Command::new("sync").status();
+    fn disable_cow(&self, _file: &File) -> CowStatus {
+        println!("⚠️  ZFS cannot overwrite in place: old blocks are only freed");
+        CowStatus::Unavoidable
+    }
+
+    fn post_wipe_cleanup(&self, _file: &File) -> Result<()> {
         Ok(())
     }
 
@@ -84,3 +138,39 @@ impl FilesystemOptimizer for ZfsOptimizer {
         false // Cannot disable CoW on ZFS
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_nocow_reported_only_when_it_sticks() {
+        // The temp dir is not btrfs, so the flag must never be reported as set
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"secret").unwrap();
+
+        let status = disable_btrfs_cow(file.as_file());
+        assert!(
+            matches!(status, CowStatus::Ignored | CowStatus::Failed(_)),
+            "{:?}",
+            status
+        );
+        assert!(!status.overwrites_in_place());
+    }
+
+    #[test]
+    fn test_nodatacow_mount_skips_ioctl() {
+        let optimizer = BtrfsOptimizer {
+            options: MountOptions {
+                nodatacow: true,
+                ..MountOptions::default()
+            },
+        };
+        // A handle the ioctl would fail on
+        let status = optimizer.disable_cow(&File::open("/dev/null").unwrap());
+        assert_eq!(status, CowStatus::NodatacowMount);
+        assert!(status.overwrites_in_place());
+    }
+}
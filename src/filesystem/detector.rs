@@ -1,112 +1,278 @@
-use super::{FilesystemOptimizer, FilesystemType};
+use super::f2fs::TrimStatus;
+use super::mountinfo::{read_mountinfo, MountEntry, MountOptions};
+use super::{ensure_writable, FilesystemInfo, FilesystemOptimizer, FilesystemType};
+use crate::sysroot::SysRoot;
 use crate::Result;
-use std::fs;
-use std::path::Path;
-use std::process::Command;
+use anyhow::Context;
+use nix::sys::stat::{major, minor};
+use nix::sys::statfs::{fstatfs, statfs};
+use std::fs::{self, File};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// `statfs(2)` `f_type` values of the filesystems we know by name. ext2/3/4
+/// share one magic; mountinfo tells them apart when it is available.
+const FILESYSTEM_MAGIC: &[(u32, &str)] = &[
+    (0xEF53, "ext4"),
+    (0x9123_683E, "btrfs"),
+    (0x5846_5342, "xfs"),
+    (0x2FC1_2FC1, "zfs"),
+    (0xF2F5_2010, "f2fs"),
+    (0x4D44, "vfat"),
+    (0x2011_BAB0, "exfat"),
+    (0x7366_746E, "ntfs3"),
+    (0x5346_544E, "ntfs"),
+    (0x794C_7630, "overlay"),
+    (0xF15F, "ecryptfs"),
+    (0x0102_1994, "tmpfs"),
+    (0x8584_58F6, "ramfs"),
+    (0x6969, "nfs"),
+    (0xFF53_4D42, "cifs"),
+    (0xFE53_4D42, "smb3"),
+    (0x0102_1997, "9p"),
+    (0x00C3_6400, "ceph"),
+    (0x6B41_4653, "afs"),
+    (0x6573_5546, "fuse"),
+];
+
+/// Filesystem types whose data is stored on another machine
+const NETWORK_FILESYSTEMS: &[&str] = &[
+    "nfs", "nfs4", "cifs", "smb3", "smbfs", "9p", "ceph", "afs", "lustre", "gfs2", "ocfs2",
+];
 
 pub fn detect_filesystem_type(path: &Path) -> Result<FilesystemType> {
-    // Try to get filesystem type using /proc/mounts
-    let mounts = fs::read_to_string("/proc/mounts")?;
-    let device = get_device_for_path(path)?;
-
-    for line in mounts.lines() {
-        let fields: Vec<&str> = line.split_whitespace().collect();
-        if fields.len() >= 3 && fields[0].contains(&device) {
-            let fs_type = fields[2];
-            return parse_filesystem_type(fs_type, &fields[0]);
-        }
-    }
+    Ok(detect_filesystem(path)?.fs_type)
+}
 
-    // Fallback to stat command
-    match Command::new("stat")
-        .arg("-f")
-        .arg("-c")
-        .arg("%T")
-        .arg(path)
-        .output()
-    {
-        Ok(output) if output.status.success() => {
-            let fs_type = String::from_utf8_lossy(&output.stdout);
-            parse_filesystem_type(fs_type.trim(), "unknown")
-        }
-        _ => Ok(FilesystemType::Unknown),
+/// Identify the filesystem holding `path` and the mount it is reached
+/// through, using `statfs` and `/proc/self/mountinfo` only.
+pub fn detect_filesystem(path: &Path) -> Result<FilesystemInfo> {
+    let path =
+        fs::canonicalize(path).with_context(|| format!("Failed to resolve {}", path.display()))?;
+    let dev = fs::metadata(&path)?.dev();
+    let magic = statfs(&path)
+        .ok()
+        .map(|stat| stat.filesystem_type().0 as u32);
+
+    Ok(detect_filesystem_at(&SysRoot::host(), &path, dev, magic))
+}
+
+/// Identify the filesystem holding an open file. `path` is only used to
+/// find its mount and should be the file's canonical path.
+pub fn detect_filesystem_of(file: &File, path: &Path) -> Result<FilesystemInfo> {
+    let dev = file.metadata()?.dev();
+    let magic = fstatfs(file)
+        .ok()
+        .map(|stat| stat.filesystem_type().0 as u32);
+
+    Ok(detect_filesystem_at(&SysRoot::host(), path, dev, magic))
+}
+
+/// Classify the filesystem for an already-resolved path, its `st_dev` and
+/// `statfs` magic, reading mount state from `root`.
+pub fn detect_filesystem_at(
+    root: &SysRoot,
+    path: &Path,
+    dev: u64,
+    magic: Option<u32>,
+) -> FilesystemInfo {
+    let mounts = read_mountinfo(root).unwrap_or_default();
+    let (mount, same_device) = match find_mount(&mounts, path, dev) {
+        Some((entry, same_device)) => (Some(entry.clone()), same_device),
+        None => (None, false),
+    };
+
+    // A mount found by device number names the filesystem exactly (and can
+    // tell ext3 from ext4); otherwise trust the superblock magic.
+    let name = match (&mount, magic.and_then(filesystem_name_from_magic)) {
+        (Some(entry), _) if same_device => Some(entry.fs_type.as_str()),
+        (_, Some(name)) => Some(name),
+        (Some(entry), None) => Some(entry.fs_type.as_str()),
+        (None, None) => None,
+    };
+
+    let options = mount
+        .as_ref()
+        .map(MountOptions::from_entry)
+        .unwrap_or_default();
+
+    let fs_type = match name.map(parse_filesystem_type) {
+        Some(FilesystemType::Ext4 { .. }) => FilesystemType::Ext4 {
+            has_journal: check_ext4_journal(root, dev),
+            data_journal: options.data_journal,
+        },
+        Some(FilesystemType::Btrfs { .. }) => FilesystemType::Btrfs {
+            subvolume: mount.as_ref().is_some_and(is_subvolume_mount),
+            compression: options.compression.clone(),
+            nodatacow: options.nodatacow,
+        },
+        Some(FilesystemType::Xfs { .. }) => FilesystemType::Xfs {
+            realtime: options.rtdev.is_some(),
+        },
+        // Dataset properties are neither in mountinfo nor exported by the
+        // kernel module, so assume compression (the default on current
+        // OpenZFS pools): passes then stay incompressible either way
+        Some(FilesystemType::Zfs { .. }) => FilesystemType::Zfs {
+            compression: true,
+            dedup: false,
+        },
+        Some(FilesystemType::Overlay { .. }) => FilesystemType::Overlay {
+            upperdir: options.upperdir.as_ref().map(PathBuf::from),
+            lowerdirs: options.lowerdirs.iter().map(PathBuf::from).collect(),
+        },
+        Some(FilesystemType::Ecryptfs { .. }) => FilesystemType::Ecryptfs {
+            lower: mount.as_ref().map(|entry| PathBuf::from(&entry.source)),
+        },
+        Some(other) => other,
+        None => FilesystemType::Unknown,
+    };
+
+    FilesystemInfo {
+        fs_type,
+        mount,
+        options,
     }
 }
 
-fn get_device_for_path(path: &Path) -> Result<String> {
-    match Command::new("df").arg("-T").arg(path).output() {
-        Ok(output) if output.status.success() => {
-            let output_str = String::from_utf8_lossy(&output.stdout);
-            let lines: Vec<&str> = output_str.lines().collect();
-            if lines.len() > 1 {
-                let fields: Vec<&str> = lines[1].split_whitespace().collect();
-                if !fields.is_empty() {
-                    return Ok(fields[0].to_string());
-                }
-            }
-            Ok("unknown".to_string())
-        }
-        _ => Ok("unknown".to_string()),
+/// Pick the mount `path` lives under: the longest mount point that is a
+/// prefix of the path, preferring mounts of the file's own device. Entries
+/// for the same mount point later in the table shadow earlier ones.
+///
+/// Returns the entry and whether it matched by device number. Btrfs
+/// subvolumes report an anonymous `st_dev` that only the prefix finds.
+fn find_mount<'a>(
+    mounts: &'a [MountEntry],
+    path: &Path,
+    dev: u64,
+) -> Option<(&'a MountEntry, bool)> {
+    let longest_prefix = |candidates: &mut dyn Iterator<Item = &'a MountEntry>| {
+        candidates
+            .filter(|entry| path.starts_with(&entry.mount_point))
+            .enumerate()
+            .max_by_key(|(index, entry)| (entry.mount_point.components().count(), *index))
+            .map(|(_, entry)| entry)
+    };
+
+    let same_device = longest_prefix(
+        &mut mounts
+            .iter()
+            .filter(|entry| entry.major == major(dev) && entry.minor == minor(dev)),
+    );
+
+    match same_device {
+        Some(entry) => Some((entry, true)),
+        None => longest_prefix(&mut mounts.iter()).map(|entry| (entry, false)),
     }
 }
 
-fn parse_filesystem_type(fs_type: &str, device: &str) -> Result<FilesystemType> {
+fn filesystem_name_from_magic(magic: u32) -> Option<&'static str> {
+    FILESYSTEM_MAGIC
+        .iter()
+        .find(|(known, _)| *known == magic)
+        .map(|(_, name)| *name)
+}
+
+fn parse_filesystem_type(fs_type: &str) -> FilesystemType {
     match fs_type.to_lowercase().as_str() {
-        "ext4" => {
-            let has_journal = check_ext4_journal(device);
-            Ok(FilesystemType::Ext4 { has_journal })
-        }
-        "btrfs" => {
-            let subvolume = device.contains("subvol");
-            Ok(FilesystemType::Btrfs { subvolume })
-        }
-        "xfs" => {
-            let realtime = check_xfs_realtime(device);
-            Ok(FilesystemType::Xfs { realtime })
-        }
-        "zfs" => {
-            let compression = check_zfs_compression(device);
-            Ok(FilesystemType::Zfs { compression })
-        }
-        "f2fs" => Ok(FilesystemType::F2fs),
-        _ => Ok(FilesystemType::Unknown),
+        // ext2 and ext3 are served by the ext4 driver; the journal check
+        // tells them apart where it matters
+        "ext4" | "ext3" | "ext2" => FilesystemType::Ext4 {
+            has_journal: true,
+            data_journal: false,
+        },
+        "btrfs" => FilesystemType::Btrfs {
+            subvolume: false,
+            compression: None,
+            nodatacow: false,
+        },
+        "xfs" => FilesystemType::Xfs { realtime: false },
+        "zfs" => FilesystemType::Zfs {
+            compression: true,
+            dedup: false,
+        },
+        "f2fs" => FilesystemType::F2fs,
+        "vfat" => FilesystemType::Fat { long_names: true },
+        "msdos" => FilesystemType::Fat { long_names: false },
+        "exfat" => FilesystemType::Exfat,
+        "ntfs3" | "ntfs" => FilesystemType::Ntfs,
+        "ecryptfs" => FilesystemType::Ecryptfs { lower: None },
+        "tmpfs" => FilesystemType::Tmpfs,
+        "ramfs" => FilesystemType::Ramfs,
+        "overlay" => FilesystemType::Overlay {
+            upperdir: None,
+            lowerdirs: Vec::new(),
+        },
+        // fuseblk drivers (ntfs-3g, exfat-fuse) store data on a local
+        // block device like an in-kernel filesystem would
+        "fuseblk" => FilesystemType::Unknown,
+        "fuse" => FilesystemType::Fuse {
+            subtype: "fuse".to_string(),
+        },
+        name if name.starts_with("fuse.") => FilesystemType::Fuse {
+            subtype: name["fuse.".len()..].to_string(),
+        },
+        name if NETWORK_FILESYSTEMS.contains(&name) => FilesystemType::Network {
+            protocol: name.to_string(),
+        },
+        _ => FilesystemType::Unknown,
     }
 }
 
-fn check_ext4_journal(device: &str) -> bool {
-    // Check if ext4 has journaling enabled
-    match Command::new("dumpe2fs").arg("-h").arg(device).output() {
-        Ok(output) if output.status.success() => {
-            let output_str = String::from_utf8_lossy(&output.stdout);
-            output_str.contains("has_journal")
-        }
-        _ => true, // Assume journal is enabled by default
-    }
+/// jbd2 registers `/proc/fs/jbd2/<device>-<inode>` for every mounted
+/// journal, so a missing entry means the filesystem runs without one.
+fn check_ext4_journal(root: &SysRoot, dev: u64) -> bool {
+    let sys_dev = root.sys(format!("dev/block/{}:{}", major(dev), minor(dev)));
+    let Some(device) = fs::canonicalize(sys_dev).ok().and_then(|path| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+    }) else {
+        return true; // Assume journal is enabled by default
+    };
+
+    let prefix = format!("{}-", device);
+    fs::read_dir(root.proc("fs/jbd2"))
+        .map(|entries| {
+            entries
+                .flatten()
+                .any(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        })
+        .unwrap_or(false)
 }
 
-fn check_xfs_realtime(_device: &str) -> bool {
-    // For now, assume no realtime volumes
-    false
+fn is_subvolume_mount(entry: &MountEntry) -> bool {
+    entry.root != Path::new("/")
 }
 
-fn check_zfs_compression(_device: &str) -> bool {
-    // For now, assume compression is enabled
-    true
+/// Shared note for mounts with online discard
+fn report_discard(options: &MountOptions) {
+    if options.discard {
+        println!("   Online discard is enabled: freed blocks are trimmed on unlink");
+    }
 }
 
 // Filesystem-specific optimizers
-pub struct Ext4Optimizer;
+pub struct Ext4Optimizer {
+    pub options: MountOptions,
+    pub has_journal: bool,
+}
 
 impl FilesystemOptimizer for Ext4Optimizer {
     fn pre_wipe_setup(&self, path: &Path) -> Result<()> {
+        ensure_writable(&self.options, path)?;
+
         println!("📁 Ext4 filesystem detected: {}", path.display());
-        println!("   Journaling considerations apply");
+        if self.options.data_journal {
+            println!("⚠️  Mounted with data=journal: every pass is written to the journal first");
+            println!("   Earlier contents may survive in the journal until it is reused");
+        }
+        if self.has_journal {
+            println!("   The journal is checkpointed and cleared once all files are wiped");
+        }
+        report_discard(&self.options);
         Ok(())
     }
 
-    fn post_wipe_cleanup(&self, _path: &Path) -> Result<()> {
-        // Sync to ensure journal writes are flushed
-        let _ = Command::new("sync").status();
+    fn post_wipe_cleanup(&self, _file: &File) -> Result<()> {
         Ok(())
     }
 
@@ -119,16 +285,26 @@ impl FilesystemOptimizer for Ext4Optimizer {
     }
 }
 
-pub struct XfsOptimizer;
+pub struct XfsOptimizer {
+    pub options: MountOptions,
+}
 
 impl FilesystemOptimizer for XfsOptimizer {
     fn pre_wipe_setup(&self, path: &Path) -> Result<()> {
+        ensure_writable(&self.options, path)?;
+
         println!("📁 XFS filesystem detected: {}", path.display());
+        if let Some(rtdev) = &self.options.rtdev {
+            println!(
+                "   Realtime device {} is configured: file data may live there",
+                rtdev
+            );
+        }
+        report_discard(&self.options);
         Ok(())
     }
 
-    fn post_wipe_cleanup(&self, _path: &Path) -> Result<()> {
-        let _ = Command::new("sync").status();
+    fn post_wipe_cleanup(&self, _file: &File) -> Result<()> {
         Ok(())
     }
 
@@ -141,17 +317,29 @@ impl FilesystemOptimizer for XfsOptimizer {
     }
 }
 
-pub struct F2fsOptimizer;
+pub struct F2fsOptimizer {
+    pub options: MountOptions,
+}
 
 impl FilesystemOptimizer for F2fsOptimizer {
     fn pre_wipe_setup(&self, path: &Path) -> Result<()> {
+        ensure_writable(&self.options, path)?;
+
         println!("📁 F2FS filesystem detected: {}", path.display());
-        println!("   Flash-friendly filesystem optimizations apply");
+        println!("   File blocks are securely trimmed after the overwrite passes");
         Ok(())
     }
 
-    fn post_wipe_cleanup(&self, _path: &Path) -> Result<()> {
-        let _ = Command::new("sync").status();
+    fn trim_file(&self, file: &File) -> TrimStatus {
+        let status = super::f2fs::sec_trim_file(file);
+        match &status {
+            TrimStatus::DiscardedAndZeroed | TrimStatus::Zeroed => println!("✅ F2FS: {}", status),
+            _ => println!("⚠️  F2FS: {}", status),
+        }
+        status
+    }
+
+    fn post_wipe_cleanup(&self, _file: &File) -> Result<()> {
         Ok(())
     }
 
@@ -163,3 +351,197 @@ impl FilesystemOptimizer for F2fsOptimizer {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::stat::makedev;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> SysRoot {
+        SysRoot::new(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(name),
+        )
+    }
+
+    fn detect(fixture_name: &str, path: &str, dev: u64, magic: Option<u32>) -> FilesystemInfo {
+        detect_filesystem_at(&fixture(fixture_name), Path::new(path), dev, magic)
+    }
+
+    #[test]
+    fn test_ext4_journal_fixtures() {
+        let nvme = detect(
+            "nvme",
+            "/home/user/notes.txt",
+            makedev(259, 2),
+            Some(0xEF53),
+        );
+        assert!(matches!(
+            nvme.fs_type,
+            FilesystemType::Ext4 {
+                has_journal: true,
+                data_journal: false
+            }
+        ));
+
+        let md = detect("md", "/srv/raid/a", makedev(9, 0), None);
+        assert!(matches!(
+            md.fs_type,
+            FilesystemType::Ext4 {
+                has_journal: true,
+                ..
+            }
+        ));
+
+        // No jbd2 entry for the loop device: ext4 without a journal
+        let image = detect("loop", "/mnt/image/a", makedev(7, 0), Some(0xEF53));
+        assert!(matches!(
+            image.fs_type,
+            FilesystemType::Ext4 {
+                has_journal: false,
+                ..
+            }
+        ));
+        assert!(image.options.read_only);
+    }
+
+    #[test]
+    fn test_mount_option_policy() {
+        let root = detect("sata-ssd", "/etc/passwd", makedev(8, 2), Some(0xEF53));
+        assert!(matches!(
+            root.fs_type,
+            FilesystemType::Ext4 {
+                data_journal: true,
+                ..
+            }
+        ));
+        assert!(root.options.discard);
+
+        // The loop image is mounted read-only
+        let image = detect("loop", "/mnt/image/a", makedev(7, 0), Some(0xEF53));
+        let error = image
+            .get_optimizer()
+            .pre_wipe_setup(Path::new("/mnt/image/a"))
+            .unwrap_err();
+        assert!(error.downcast_ref::<crate::NotWipeable>().is_some());
+    }
+
+    #[test]
+    fn test_longest_prefix_mount() {
+        let home = detect("dm-crypt", "/home/user/a", makedev(254, 0), None);
+        let mount = home.mount.unwrap();
+        assert_eq!(mount.mount_point, PathBuf::from("/home"));
+        assert_eq!(mount.root, PathBuf::from("/@home"));
+        assert_eq!(mount.source, "/dev/mapper/luks-3f2a");
+        assert!(mount.super_options.contains(&"subvol=/@home".to_string()));
+        assert!(matches!(
+            home.fs_type,
+            FilesystemType::Btrfs {
+                subvolume: true,
+                compression: None,
+                nodatacow: false
+            }
+        ));
+
+        let xfs = detect("hdd", "/srv/data/file", makedev(8, 17), Some(0x5846_5342));
+        assert_eq!(xfs.mount.unwrap().mount_point, PathBuf::from("/srv/data"));
+        assert!(matches!(xfs.fs_type, FilesystemType::Xfs { .. }));
+    }
+
+    #[test]
+    fn test_device_number_beats_prefix() {
+        // /boot/efi is on nvme0n1p1; a file on the root device that happens
+        // to sit under the same prefix (e.g. before the ESP was mounted)
+        // must not be attributed to it.
+        let efi = detect("nvme", "/boot/efi/EFI/x", makedev(259, 1), None);
+        assert_eq!(efi.mount.unwrap().source, "/dev/nvme0n1p1");
+
+        let shadowed = detect("nvme", "/boot/efi/EFI/x", makedev(259, 2), None);
+        assert_eq!(shadowed.mount.unwrap().mount_point, PathBuf::from("/"));
+    }
+
+    #[test]
+    fn test_anonymous_device_uses_magic() {
+        // Nested btrfs subvolumes report an st_dev that is not in mountinfo
+        let nested = detect(
+            "dm-crypt",
+            "/home/user/.snapshots/1/a",
+            makedev(0, 52),
+            Some(0x9123_683E),
+        );
+        assert_eq!(nested.mount.unwrap().mount_point, PathBuf::from("/home"));
+        assert!(matches!(nested.fs_type, FilesystemType::Btrfs { .. }));
+    }
+
+    #[test]
+    fn test_magic_without_mountinfo() {
+        let info = detect("nonexistent", "/data/a", makedev(8, 1), Some(0xF2F5_2010));
+        assert!(info.mount.is_none());
+        assert!(matches!(info.fs_type, FilesystemType::F2fs));
+    }
+
+    #[test]
+    fn test_ext_family_types() {
+        for name in ["ext2", "ext3", "ext4", "EXT3"] {
+            assert!(
+                matches!(parse_filesystem_type(name), FilesystemType::Ext4 { .. }),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_network_and_fuse_types() {
+        assert!(matches!(
+            parse_filesystem_type("nfs4"),
+            FilesystemType::Network { protocol } if protocol == "nfs4"
+        ));
+        assert!(matches!(
+            parse_filesystem_type("cifs"),
+            FilesystemType::Network { .. }
+        ));
+        assert!(matches!(
+            parse_filesystem_type("fuse.sshfs"),
+            FilesystemType::Fuse { subtype } if subtype == "sshfs"
+        ));
+        assert!(!parse_filesystem_type("fuseblk").is_remote());
+
+        let info = detect(
+            "nonexistent",
+            "/mnt/share/a",
+            makedev(0, 52),
+            Some(0xFE53_4D42),
+        );
+        assert!(info.fs_type.is_remote());
+    }
+
+    #[test]
+    fn test_removable_media_types() {
+        assert!(matches!(
+            parse_filesystem_type("vfat"),
+            FilesystemType::Fat { long_names: true }
+        ));
+        assert!(matches!(
+            parse_filesystem_type("msdos"),
+            FilesystemType::Fat { long_names: false }
+        ));
+        assert!(matches!(
+            parse_filesystem_type("exfat"),
+            FilesystemType::Exfat
+        ));
+        assert!(matches!(
+            parse_filesystem_type("ntfs3"),
+            FilesystemType::Ntfs
+        ));
+
+        let info = detect("nonexistent", "/media/usb/a", makedev(8, 17), Some(0x4D44));
+        assert!(matches!(
+            info.fs_type,
+            FilesystemType::Fat { long_names: true }
+        ));
+        assert!(info.get_optimizer().name_rules().is_some());
+    }
+}
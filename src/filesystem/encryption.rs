@@ -0,0 +1,253 @@
+use super::{FilesystemInfo, FilesystemType};
+use nix::errno::Errno;
+use nix::{ioctl_read_bad, ioctl_readwrite, ioctl_readwrite_bad};
+use nix::{request_code_readwrite, request_code_write};
+use std::fmt;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+const FSCRYPT_KEY_SPEC_TYPE_DESCRIPTOR: u32 = 1;
+const FSCRYPT_KEY_SPEC_TYPE_IDENTIFIER: u32 = 2;
+const FSCRYPT_KEY_STATUS_PRESENT: u32 = 2;
+const FSCRYPT_KEY_STATUS_INCOMPLETELY_REMOVED: u32 = 3;
+
+/// struct fscrypt_get_policy_ex_arg, sized for the largest (v2) policy
+#[repr(C)]
+struct FscryptGetPolicyExArg {
+    policy_size: u64,
+    policy: [u8; 24],
+}
+
+/// struct fscrypt_get_key_status_arg
+#[repr(C)]
+struct FscryptGetKeyStatusArg {
+    key_spec_type: u32,
+    key_spec_reserved: u32,
+    key_spec: [u8; 32],
+    reserved: [u32; 6],
+    status: u32,
+    status_flags: u32,
+    user_count: u32,
+    out_reserved: [u32; 13],
+}
+
+// Declared in linux/fscrypt.h with the sizes below, not the struct sizes
+ioctl_readwrite_bad!(
+    fs_ioc_get_encryption_policy_ex,
+    request_code_readwrite!(b'f', 22, 9),
+    FscryptGetPolicyExArg
+);
+ioctl_read_bad!(
+    fs_ioc_get_encryption_policy,
+    request_code_write!(b'f', 21, 12),
+    [u8; 12]
+);
+ioctl_readwrite!(
+    fs_ioc_get_encryption_key_status,
+    b'f',
+    26,
+    FscryptGetKeyStatusArg
+);
+
+/// How a file's contents are encrypted at rest. When they are, removing
+/// the key makes the data unrecoverable regardless of what overwriting
+/// leaves behind.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Encryption {
+    #[default]
+    None,
+    Fscrypt(FscryptPolicy),
+    /// eCryptfs stacked on `lower`, which holds the ciphertext
+    Ecryptfs {
+        lower: Option<PathBuf>,
+        /// `ecryptfs_sig` and `ecryptfs_fnek_sig` key signatures
+        signatures: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FscryptPolicy {
+    pub version: u8,
+    /// Master key descriptor (v1) or identifier (v2), in hex
+    pub identifier: String,
+    pub key: KeyStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyStatus {
+    Present,
+    Absent,
+    /// Removed, but files still open keep it in use
+    IncompletelyRemoved,
+    /// v1 keys in a process keyring, or a kernel without the status ioctl
+    Unknown,
+}
+
+impl fmt::Display for KeyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyStatus::Present => write!(f, "key loaded"),
+            KeyStatus::Absent => write!(f, "key not loaded"),
+            KeyStatus::IncompletelyRemoved => write!(f, "key removed but still in use"),
+            KeyStatus::Unknown => write!(f, "key status unknown"),
+        }
+    }
+}
+
+impl fmt::Display for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encryption::None => write!(f, "none"),
+            Encryption::Fscrypt(policy) => {
+                write!(f, "fscrypt v{} policy, {}", policy.version, policy.key)
+            }
+            Encryption::Ecryptfs {
+                lower: Some(lower), ..
+            } => write!(f, "eCryptfs over {}", lower.display()),
+            Encryption::Ecryptfs { lower: None, .. } => write!(f, "eCryptfs"),
+        }
+    }
+}
+
+impl Encryption {
+    /// Key identifiers whose removal makes the data unrecoverable
+    pub fn key_identifiers(&self) -> Vec<String> {
+        match self {
+            Encryption::None => Vec::new(),
+            Encryption::Fscrypt(policy) => vec![policy.identifier.clone()],
+            Encryption::Ecryptfs { signatures, .. } => signatures.clone(),
+        }
+    }
+}
+
+/// Find out whether the entries of `dir` are stored encrypted. fscrypt
+/// policies are inherited from the directory, which can be queried even
+/// without the key, so the directory is asked rather than the file.
+pub fn detect_encryption(dir: &File, filesystem: &FilesystemInfo) -> Encryption {
+    if let FilesystemType::Ecryptfs { lower } = &filesystem.fs_type {
+        return Encryption::Ecryptfs {
+            lower: lower.clone(),
+            signatures: filesystem.options.ecryptfs_sigs.clone(),
+        };
+    }
+
+    match fscrypt_policy(dir) {
+        Some(policy) => Encryption::Fscrypt(policy),
+        None => Encryption::None,
+    }
+}
+
+/// Read the fscrypt policy of an open file or directory, using the v1-only
+/// ioctl on kernels older than 5.4
+pub fn fscrypt_policy(handle: &File) -> Option<FscryptPolicy> {
+    let mut arg = FscryptGetPolicyExArg {
+        policy_size: 24,
+        policy: [0; 24],
+    };
+    let policy = match unsafe { fs_ioc_get_encryption_policy_ex(handle.as_raw_fd(), &mut arg) } {
+        Ok(_) => arg.policy,
+        Err(Errno::ENOTTY) => {
+            let mut v1 = [0u8; 12];
+            unsafe { fs_ioc_get_encryption_policy(handle.as_raw_fd(), &mut v1) }.ok()?;
+            let mut policy = [0u8; 24];
+            policy[..12].copy_from_slice(&v1);
+            policy
+        }
+        // ENODATA: not encrypted; EOPNOTSUPP: filesystem without fscrypt
+        Err(_) => return None,
+    };
+
+    let (version, key_spec_type, key) = match policy[0] {
+        // FSCRYPT_POLICY_V1 is 0: descriptor after mode and flag bytes
+        0 => (1, FSCRYPT_KEY_SPEC_TYPE_DESCRIPTOR, &policy[4..12]),
+        // FSCRYPT_POLICY_V2: identifier after four mode/flag and four
+        // reserved bytes
+        2 => (2, FSCRYPT_KEY_SPEC_TYPE_IDENTIFIER, &policy[8..24]),
+        _ => return None,
+    };
+
+    let key_status = key_status(handle, key_spec_type, key);
+    Some(FscryptPolicy {
+        version,
+        identifier: to_hex(key),
+        key: match (version, key_status) {
+            // v1 keys normally live in a session keyring the filesystem
+            // can't see, so "absent" proves nothing
+            (1, KeyStatus::Absent) => KeyStatus::Unknown,
+            (_, status) => status,
+        },
+    })
+}
+
+fn key_status(handle: &File, key_spec_type: u32, key: &[u8]) -> KeyStatus {
+    let mut arg = FscryptGetKeyStatusArg {
+        key_spec_type,
+        key_spec_reserved: 0,
+        key_spec: [0; 32],
+        reserved: [0; 6],
+        status: 0,
+        status_flags: 0,
+        user_count: 0,
+        out_reserved: [0; 13],
+    };
+    arg.key_spec[..key.len()].copy_from_slice(key);
+
+    match unsafe { fs_ioc_get_encryption_key_status(handle.as_raw_fd(), &mut arg) } {
+        Ok(_) => match arg.status {
+            FSCRYPT_KEY_STATUS_PRESENT => KeyStatus::Present,
+            FSCRYPT_KEY_STATUS_INCOMPLETELY_REMOVED => KeyStatus::IncompletelyRemoved,
+            _ => KeyStatus::Absent,
+        },
+        Err(_) => KeyStatus::Unknown,
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::mountinfo::MountOptions;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_unencrypted_directory() {
+        let dir = tempdir().unwrap();
+        let handle = File::open(dir.path()).unwrap();
+        assert_eq!(fscrypt_policy(&handle), None);
+        assert_eq!(
+            detect_encryption(&handle, &FilesystemInfo::unknown()),
+            Encryption::None
+        );
+    }
+
+    #[test]
+    fn test_ecryptfs_from_mount() {
+        let filesystem = FilesystemInfo {
+            fs_type: FilesystemType::Ecryptfs {
+                lower: Some(PathBuf::from("/home/.ecryptfs/alice/.Private")),
+            },
+            mount: None,
+            options: MountOptions {
+                ecryptfs_sigs: vec!["d395309aaad4de06".to_string()],
+                ..MountOptions::default()
+            },
+        };
+
+        let dir = File::open(std::env::temp_dir()).unwrap();
+        let encryption = detect_encryption(&dir, &filesystem);
+        assert_eq!(
+            encryption.to_string(),
+            "eCryptfs over /home/.ecryptfs/alice/.Private"
+        );
+        assert_eq!(encryption.key_identifiers(), vec!["d395309aaad4de06"]);
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0x00, 0xab, 0x10]), "00ab10");
+    }
+}
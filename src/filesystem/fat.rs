@@ -0,0 +1,264 @@
+use super::mountinfo::MountOptions;
+use super::{ensure_writable, FilesystemOptimizer};
+use crate::Result;
+use rand::Rng;
+use std::fs::File;
+use std::path::Path;
+
+/// Characters of generated 8.3 names; stored as-is in the short entry
+const SHORT_NAME_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+/// Characters of generated long names, valid on FAT, exFAT and NTFS alike
+const LONG_NAME_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+/// Valid in long names but not in 8.3 names, so a one-character name made
+/// of one of these still gets a long-name entry
+const LONG_ONLY_CHARS: &[u8] = b"+,;=[]";
+/// Punctuation allowed in 8.3 names besides letters and digits
+const SHORT_NAME_PUNCTUATION: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// How a filesystem lays out a name in its directory entries. A deleted
+/// entry keeps the name (FAT only loses the first byte of the 8.3 name),
+/// and freed slots are reused by the next name needing the same number of
+/// them, so replacement names must take exactly as many slots as the
+/// original.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameRules {
+    /// One 8.3 entry, plus long-filename entries of 13 UTF-16 units each
+    /// unless the name fits 8.3 (`long_names` is false for msdos mounts)
+    Fat { long_names: bool },
+    /// File and stream entries plus one name entry per 15 UTF-16 units
+    Exfat,
+    /// A `$FILE_NAME` attribute and index entry sized by the UTF-16 length
+    Ntfs,
+}
+
+impl NameRules {
+    /// A random name occupying the same directory entry slots as `name`
+    pub fn same_length_name<R: Rng>(&self, name: &str, rng: &mut R) -> String {
+        match self {
+            NameRules::Fat { long_names: false } => {
+                let (base, extension) = split_extension(name);
+                short_name(base.len().clamp(1, 8), extension.len().min(3), rng)
+            }
+            NameRules::Fat { long_names: true } => match fits_short_name(name) {
+                Some((base, extension)) => short_name(base, extension, rng),
+                None => long_name(utf16_len(name), rng),
+            },
+            NameRules::Exfat | NameRules::Ntfs => {
+                random_chars(LONG_NAME_CHARS, utf16_len(name).max(1), rng)
+            }
+        }
+    }
+}
+
+/// Base and extension lengths of a name vfat stores in a lone 8.3 entry:
+/// valid 8.3 characters, and each part entirely upper or lower case
+fn fits_short_name(name: &str) -> Option<(usize, usize)> {
+    if name.starts_with('.') || name.matches('.').count() > 1 {
+        return None;
+    }
+    let (base, extension) = split_extension(name);
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return None;
+    }
+
+    let valid = |part: &str| {
+        part.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || SHORT_NAME_PUNCTUATION.contains(&b))
+            && !(part.bytes().any(|b| b.is_ascii_lowercase())
+                && part.bytes().any(|b| b.is_ascii_uppercase()))
+    };
+    (valid(base) && valid(extension)).then_some((base.len(), extension.len()))
+}
+
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    }
+}
+
+fn utf16_len(name: &str) -> usize {
+    name.encode_utf16().count()
+}
+
+fn short_name<R: Rng>(base: usize, extension: usize, rng: &mut R) -> String {
+    let mut name = random_chars(SHORT_NAME_CHARS, base, rng);
+    if extension > 0 {
+        name.push('.');
+        name.push_str(&random_chars(SHORT_NAME_CHARS, extension, rng));
+    }
+    name
+}
+
+/// A name that can't be stored as a lone 8.3 entry: mixed case, or a
+/// character only long names allow
+fn long_name<R: Rng>(len: usize, rng: &mut R) -> String {
+    if len <= 1 {
+        return random_chars(LONG_ONLY_CHARS, 1, rng);
+    }
+    let mut name = String::with_capacity(len);
+    name.push(rng.gen_range(b'A'..=b'Z') as char);
+    name.push(rng.gen_range(b'a'..=b'z') as char);
+    name.push_str(&random_chars(LONG_NAME_CHARS, len - 2, rng));
+    name
+}
+
+fn random_chars<R: Rng>(chars: &[u8], len: usize, rng: &mut R) -> String {
+    (0..len)
+        .map(|_| chars[rng.gen_range(0..chars.len())] as char)
+        .collect()
+}
+
+pub struct FatOptimizer {
+    pub options: MountOptions,
+    pub long_names: bool,
+}
+
+impl FilesystemOptimizer for FatOptimizer {
+    fn pre_wipe_setup(&self, path: &Path) -> Result<()> {
+        ensure_writable(&self.options, path)?;
+
+        println!("📁 FAT filesystem detected: {}", path.display());
+        println!(
+            "   Renames keep the name's length so its 8.3 and long-name entries are overwritten"
+        );
+        Ok(())
+    }
+
+    fn post_wipe_cleanup(&self, _file: &File) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_recommended_passes(&self) -> usize {
+        1 // Mostly found on flash media
+    }
+
+    fn should_disable_cow(&self) -> bool {
+        false
+    }
+
+    fn name_rules(&self) -> Option<NameRules> {
+        Some(NameRules::Fat {
+            long_names: self.long_names,
+        })
+    }
+}
+
+pub struct ExfatOptimizer {
+    pub options: MountOptions,
+}
+
+impl FilesystemOptimizer for ExfatOptimizer {
+    fn pre_wipe_setup(&self, path: &Path) -> Result<()> {
+        ensure_writable(&self.options, path)?;
+
+        println!("📁 exFAT filesystem detected: {}", path.display());
+        println!("   Renames keep the name's length so its name entries are overwritten");
+        Ok(())
+    }
+
+    fn post_wipe_cleanup(&self, _file: &File) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_recommended_passes(&self) -> usize {
+        1 // Mostly found on flash media
+    }
+
+    fn should_disable_cow(&self) -> bool {
+        false
+    }
+
+    fn name_rules(&self) -> Option<NameRules> {
+        Some(NameRules::Exfat)
+    }
+}
+
+pub struct NtfsOptimizer {
+    pub options: MountOptions,
+}
+
+impl FilesystemOptimizer for NtfsOptimizer {
+    fn pre_wipe_setup(&self, path: &Path) -> Result<()> {
+        ensure_writable(&self.options, path)?;
+
+        println!("📁 NTFS filesystem detected: {}", path.display());
+        println!("   Renames keep the name's length so its MFT and index entries are overwritten");
+        println!("   Note: $LogFile may still hold old names until it wraps");
+        Ok(())
+    }
+
+    fn post_wipe_cleanup(&self, _file: &File) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_recommended_passes(&self) -> usize {
+        3
+    }
+
+    fn should_disable_cow(&self) -> bool {
+        false
+    }
+
+    fn name_rules(&self) -> Option<NameRules> {
+        Some(NameRules::Ntfs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_names_keep_layout() {
+        let mut rng = rand::thread_rng();
+        let rules = NameRules::Fat { long_names: true };
+
+        for original in ["report.txt", "README", "A.B", "photo1.JPEG.bak"] {
+            let name = rules.same_length_name(original, &mut rng);
+            assert_eq!(fits_short_name(&name), fits_short_name(original));
+        }
+
+        let name = rules.same_length_name("notes.md", &mut rng);
+        assert_eq!(fits_short_name(&name), Some((5, 2)));
+        assert!(name
+            .bytes()
+            .all(|b| b == b'.' || SHORT_NAME_CHARS.contains(&b)));
+    }
+
+    #[test]
+    fn test_long_names_keep_utf16_length() {
+        let mut rng = rand::thread_rng();
+        let rules = NameRules::Fat { long_names: true };
+
+        for original in [
+            "Report.txt",
+            "a long file name.docx",
+            "café",
+            "+",
+            "日記.txt",
+        ] {
+            let name = rules.same_length_name(original, &mut rng);
+            assert_eq!(utf16_len(&name), utf16_len(original), "{}", original);
+            assert_eq!(fits_short_name(&name), None, "{}", original);
+        }
+    }
+
+    #[test]
+    fn test_msdos_names_are_8_3() {
+        let mut rng = rand::thread_rng();
+        let rules = NameRules::Fat { long_names: false };
+        let name = rules.same_length_name("data.bin", &mut rng);
+        assert_eq!(fits_short_name(&name), Some((4, 3)));
+    }
+
+    #[test]
+    fn test_exfat_and_ntfs_names() {
+        let mut rng = rand::thread_rng();
+        for rules in [NameRules::Exfat, NameRules::Ntfs] {
+            let name = rules.same_length_name("résumé 2024.pdf", &mut rng);
+            assert_eq!(utf16_len(&name), 15);
+            assert!(name.bytes().all(|b| LONG_NAME_CHARS.contains(&b)));
+        }
+    }
+}
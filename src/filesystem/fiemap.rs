@@ -0,0 +1,247 @@
+use crate::Result;
+use anyhow::Context;
+use nix::{ioctl_readwrite_bad, request_code_readwrite};
+use std::fs::File;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+
+/// Flush dirty data before mapping so delayed allocations have a location
+const FIEMAP_FLAG_SYNC: u32 = 0x0000_0001;
+
+pub const FIEMAP_EXTENT_LAST: u32 = 0x0000_0001;
+pub const FIEMAP_EXTENT_UNKNOWN: u32 = 0x0000_0002;
+pub const FIEMAP_EXTENT_DELALLOC: u32 = 0x0000_0004;
+pub const FIEMAP_EXTENT_DATA_INLINE: u32 = 0x0000_0200;
+pub const FIEMAP_EXTENT_SHARED: u32 = 0x0000_2000;
+
+const EXTENTS_PER_CALL: usize = 64;
+
+#[repr(C)]
+#[derive(Default)]
+struct FiemapHeader {
+    fm_start: u64,
+    fm_length: u64,
+    fm_flags: u32,
+    fm_mapped_extents: u32,
+    fm_extent_count: u32,
+    fm_reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct FiemapExtent {
+    fe_logical: u64,
+    fe_physical: u64,
+    fe_length: u64,
+    fe_reserved64: [u64; 2],
+    fe_flags: u32,
+    fe_reserved: [u32; 3],
+}
+
+#[repr(C)]
+struct FiemapRequest {
+    header: FiemapHeader,
+    extents: [FiemapExtent; EXTENTS_PER_CALL],
+}
+
+// The request code is sized by the header only; the extent array follows it
+ioctl_readwrite_bad!(
+    fs_ioc_fiemap,
+    request_code_readwrite!(b'f', 11, mem::size_of::<FiemapHeader>()),
+    FiemapRequest
+);
+
+/// One mapped range of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    pub logical: u64,
+    pub physical: u64,
+    pub length: u64,
+    pub flags: u32,
+}
+
+impl Extent {
+    /// Whether `physical` is a real disk location
+    pub fn has_location(&self) -> bool {
+        self.flags & (FIEMAP_EXTENT_UNKNOWN | FIEMAP_EXTENT_DELALLOC | FIEMAP_EXTENT_DATA_INLINE)
+            == 0
+    }
+
+    pub fn is_shared(&self) -> bool {
+        self.flags & FIEMAP_EXTENT_SHARED != 0
+    }
+}
+
+/// Map every extent of `file` (see Documentation/filesystems/fiemap.rst)
+pub fn map_extents(file: &File) -> Result<Vec<Extent>> {
+    let mut extents = Vec::new();
+    let mut start = 0u64;
+
+    loop {
+        let mut request = FiemapRequest {
+            header: FiemapHeader {
+                fm_start: start,
+                fm_length: u64::MAX - start,
+                fm_flags: FIEMAP_FLAG_SYNC,
+                fm_extent_count: EXTENTS_PER_CALL as u32,
+                ..FiemapHeader::default()
+            },
+            extents: [FiemapExtent::default(); EXTENTS_PER_CALL],
+        };
+        unsafe { fs_ioc_fiemap(file.as_raw_fd(), &mut request) }.context("FS_IOC_FIEMAP failed")?;
+
+        let mapped = request.header.fm_mapped_extents as usize;
+        let batch = &request.extents[..mapped.min(EXTENTS_PER_CALL)];
+        extents.extend(batch.iter().map(|e| Extent {
+            logical: e.fe_logical,
+            physical: e.fe_physical,
+            length: e.fe_length,
+            flags: e.fe_flags,
+        }));
+
+        match batch.last() {
+            Some(last) if last.fe_flags & FIEMAP_EXTENT_LAST == 0 => {
+                start = last.fe_logical + last.fe_length;
+            }
+            _ => break,
+        }
+    }
+
+    Ok(extents)
+}
+
+/// Where the passes landed relative to the file's original blocks
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ExtentCheck {
+    #[default]
+    NotChecked,
+    /// Every original block was still mapped after the last pass
+    InPlace,
+    /// Original blocks no longer belong to the file; data likely remains
+    Relocated { bytes: u64 },
+    /// The filesystem can't map the file or reports no disk location
+    Unavailable(String),
+}
+
+impl ExtentCheck {
+    /// Compare the mappings taken before the first and after the last pass
+    pub fn compare(before: &[Extent], after: &[Extent]) -> Self {
+        if let Some(extent) = before.iter().chain(after).find(|e| !e.has_location()) {
+            return ExtentCheck::Unavailable(format!(
+                "extent at offset {} has no known disk location",
+                extent.logical
+            ));
+        }
+
+        let mut covered: Vec<(u64, u64)> = after
+            .iter()
+            .map(|e| (e.physical, e.physical + e.length))
+            .collect();
+        covered.sort_unstable();
+
+        let bytes: u64 = before
+            .iter()
+            .map(|e| uncovered_bytes(e.physical, e.physical + e.length, &covered))
+            .sum();
+
+        if bytes == 0 {
+            ExtentCheck::InPlace
+        } else {
+            ExtentCheck::Relocated { bytes }
+        }
+    }
+}
+
+/// Bytes of `[start, end)` not inside any of the sorted `ranges`
+fn uncovered_bytes(start: u64, end: u64, ranges: &[(u64, u64)]) -> u64 {
+    let mut uncovered = 0;
+    let mut cursor = start;
+
+    for &(range_start, range_end) in ranges {
+        if range_end <= cursor {
+            continue;
+        }
+        if range_start >= end {
+            break;
+        }
+        if range_start > cursor {
+            uncovered += range_start - cursor;
+        }
+        cursor = cursor.max(range_end);
+        if cursor >= end {
+            return uncovered;
+        }
+    }
+
+    uncovered + end.saturating_sub(cursor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn extent(logical: u64, physical: u64, length: u64) -> Extent {
+        Extent {
+            logical,
+            physical,
+            length,
+            flags: 0,
+        }
+    }
+
+    #[test]
+    fn test_compare_extents() {
+        let before = [extent(0, 4096, 8192), extent(8192, 65536, 4096)];
+
+        assert_eq!(ExtentCheck::compare(&before, &before), ExtentCheck::InPlace);
+
+        // Same blocks, split into more extents after the wipe
+        let split = [
+            extent(0, 4096, 4096),
+            extent(4096, 8192, 4096),
+            extent(8192, 65536, 4096),
+        ];
+        assert_eq!(ExtentCheck::compare(&before, &split), ExtentCheck::InPlace);
+
+        // Second extent rewritten elsewhere
+        let moved = [extent(0, 4096, 8192), extent(8192, 131072, 4096)];
+        assert_eq!(
+            ExtentCheck::compare(&before, &moved),
+            ExtentCheck::Relocated { bytes: 4096 }
+        );
+
+        let inline = [Extent {
+            flags: FIEMAP_EXTENT_DATA_INLINE,
+            ..extent(0, 0, 60)
+        }];
+        assert!(matches!(
+            ExtentCheck::compare(&inline, &inline),
+            ExtentCheck::Unavailable(_)
+        ));
+    }
+
+    #[test]
+    fn test_uncovered_bytes() {
+        let ranges = [(0, 100), (150, 200), (180, 300)];
+        assert_eq!(uncovered_bytes(50, 250, &ranges), 50);
+        assert_eq!(uncovered_bytes(300, 400, &ranges), 100);
+        assert_eq!(uncovered_bytes(10, 90, &ranges), 0);
+    }
+
+    #[test]
+    fn test_map_extents_of_written_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&vec![0xA5; 64 * 1024]).unwrap();
+        file.as_file().sync_all().unwrap();
+
+        // Not every filesystem implements FIEMAP (tmpfs doesn't)
+        let Ok(extents) = map_extents(file.as_file()) else {
+            return;
+        };
+        let mapped: u64 = extents.iter().map(|e| e.length).sum();
+        assert!(mapped >= 64 * 1024);
+        assert!(extents.last().unwrap().flags & FIEMAP_EXTENT_LAST != 0);
+    }
+}
@@ -0,0 +1,29 @@
+use crate::Result;
+use anyhow::Context;
+use nix::{ioctl_read_bad, ioctl_write_ptr_bad};
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+
+/// Inode flags as shown by `lsattr` (see ioctl_iflags(2))
+pub const FS_COMPR_FL: u32 = 0x0000_0004;
+pub const FS_IMMUTABLE_FL: u32 = 0x0000_0010;
+pub const FS_APPEND_FL: u32 = 0x0000_0020;
+pub const FS_NOCOMP_FL: u32 = 0x0000_0400;
+pub const FS_NOCOW_FL: u32 = 0x0080_0000;
+
+// The request codes are declared with `long`, but every filesystem reads
+// and writes a plain int through the pointer.
+ioctl_read_bad!(fs_ioc_getflags, libc::FS_IOC_GETFLAGS, libc::c_int);
+ioctl_write_ptr_bad!(fs_ioc_setflags, libc::FS_IOC_SETFLAGS, libc::c_int);
+
+pub fn get_flags(file: &File) -> Result<u32> {
+    let mut flags: libc::c_int = 0;
+    unsafe { fs_ioc_getflags(file.as_raw_fd(), &mut flags) }.context("FS_IOC_GETFLAGS failed")?;
+    Ok(flags as u32)
+}
+
+pub fn set_flags(file: &File, flags: u32) -> Result<()> {
+    let flags = flags as libc::c_int;
+    unsafe { fs_ioc_setflags(file.as_raw_fd(), &flags) }.context("FS_IOC_SETFLAGS failed")?;
+    Ok(())
+}
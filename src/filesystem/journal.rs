@@ -0,0 +1,79 @@
+use nix::errno::Errno;
+use nix::ioctl_write_ptr;
+use std::fmt;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+
+const EXT4_IOC_CHECKPOINT_FLAG_DISCARD: u32 = 0x1;
+const EXT4_IOC_CHECKPOINT_FLAG_ZEROOUT: u32 = 0x2;
+
+ioctl_write_ptr!(ext4_ioc_checkpoint, b'f', 43, u32);
+
+/// What happened to a filesystem's journal after the wipe
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum JournalCheckpoint {
+    /// No journal, or not a filesystem we know how to checkpoint
+    #[default]
+    NotApplicable,
+    /// Checkpointed and the journal area overwritten with zeros
+    Zeroed,
+    /// Checkpointed and the journal area discarded
+    Discarded,
+    Failed(String),
+}
+
+impl fmt::Display for JournalCheckpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalCheckpoint::NotApplicable => write!(f, "no journal to checkpoint"),
+            JournalCheckpoint::Zeroed => write!(f, "journal checkpointed and zeroed"),
+            JournalCheckpoint::Discarded => write!(f, "journal checkpointed and discarded"),
+            JournalCheckpoint::Failed(reason) => write!(f, "journal not cleared: {}", reason),
+        }
+    }
+}
+
+/// Flush the ext4 journal into the filesystem and wipe the journal blocks
+/// so old names and data blocks can't be recovered from it (Linux 5.13+).
+/// `handle` can be any file or directory on the filesystem.
+pub fn checkpoint_ext4(handle: &File) -> JournalCheckpoint {
+    let zeroout = EXT4_IOC_CHECKPOINT_FLAG_ZEROOUT;
+    let error = match unsafe { ext4_ioc_checkpoint(handle.as_raw_fd(), &zeroout) } {
+        Ok(_) => return JournalCheckpoint::Zeroed,
+        Err(e) => e,
+    };
+    if matches!(error, Errno::EPERM | Errno::ENOTTY | Errno::ENODEV) {
+        return JournalCheckpoint::Failed(describe_error(error));
+    }
+
+    // Some devices can't zero a range in the block layer; a discard is the
+    // next best thing where it is supported
+    let discard = EXT4_IOC_CHECKPOINT_FLAG_DISCARD;
+    match unsafe { ext4_ioc_checkpoint(handle.as_raw_fd(), &discard) } {
+        Ok(_) => JournalCheckpoint::Discarded,
+        Err(_) => JournalCheckpoint::Failed(describe_error(error)),
+    }
+}
+
+fn describe_error(error: Errno) -> String {
+    match error {
+        Errno::EPERM => "checkpointing the journal needs root (CAP_SYS_ADMIN)".to_string(),
+        Errno::ENOTTY | Errno::EINVAL => {
+            "the kernel does not support EXT4_IOC_CHECKPOINT (needs Linux 5.13+)".to_string()
+        }
+        Errno::ENODEV => "the filesystem has no journal".to_string(),
+        other => format!("EXT4_IOC_CHECKPOINT failed: {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_error() {
+        assert!(describe_error(Errno::EPERM).contains("CAP_SYS_ADMIN"));
+        assert!(describe_error(Errno::ENOTTY).contains("5.13"));
+        assert!(describe_error(Errno::EIO).contains("EXT4_IOC_CHECKPOINT failed"));
+    }
+}
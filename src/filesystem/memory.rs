@@ -0,0 +1,102 @@
+use super::mountinfo::MountOptions;
+use super::{ensure_writable, FilesystemOptimizer};
+use crate::storage::swap;
+use crate::sysroot::SysRoot;
+use crate::Result;
+use anyhow::Context;
+use nix::errno::Errno;
+use nix::fcntl::{fallocate, FallocateFlags};
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// tmpfs and ramfs: one pass replaces the pages in RAM, after which they
+/// are handed back to the kernel straight away
+pub struct MemoryOptimizer {
+    pub options: MountOptions,
+    pub swappable: bool,
+}
+
+impl FilesystemOptimizer for MemoryOptimizer {
+    fn pre_wipe_setup(&self, path: &Path) -> Result<()> {
+        ensure_writable(&self.options, path)?;
+
+        println!("📁 Memory-backed filesystem: {}", path.display());
+        if self.swappable {
+            for area in swap::unencrypted_swap(&SysRoot::host()) {
+                println!(
+                    "⚠️  Unencrypted swap {} is active: pages of this file may already have been swapped out",
+                    area.name
+                );
+                println!("   Note: Those copies stay on the swap device until overwritten");
+            }
+        }
+        Ok(())
+    }
+
+    fn post_wipe_cleanup(&self, file: &File) -> Result<()> {
+        // Punch out every page now rather than at unlink, so the freed
+        // pages don't outlive an open handle held by someone else
+        let len = file.metadata()?.len() as i64;
+        if len == 0 {
+            return Ok(());
+        }
+        punched(fallocate(
+            file.as_raw_fd(),
+            FallocateFlags::FALLOC_FL_PUNCH_HOLE | FallocateFlags::FALLOC_FL_KEEP_SIZE,
+            0,
+            len,
+        ))
+    }
+
+    fn get_recommended_passes(&self) -> usize {
+        1
+    }
+
+    fn should_disable_cow(&self) -> bool {
+        false
+    }
+}
+
+/// ramfs can't punch holes; its pages are freed at truncate and unlink,
+/// which follow anyway
+fn punched(result: nix::Result<()>) -> Result<()> {
+    match result {
+        Ok(()) | Err(Errno::EOPNOTSUPP) => Ok(()),
+        Err(e) => Err(e).context("Failed to drop pages of the wiped file"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_pages_dropped_after_wipe() {
+        // /dev/shm is tmpfs on Linux hosts; skip where it isn't available
+        let Ok(mut file) = tempfile::NamedTempFile::new_in("/dev/shm") else {
+            return;
+        };
+        file.write_all(&vec![0x5A; 64 * 1024]).unwrap();
+
+        let optimizer = MemoryOptimizer {
+            options: MountOptions::default(),
+            swappable: true,
+        };
+        optimizer.post_wipe_cleanup(file.as_file()).unwrap();
+
+        let metadata = file.as_file().metadata().unwrap();
+        assert_eq!(metadata.len(), 64 * 1024);
+        assert_eq!(metadata.blocks(), 0);
+    }
+
+    #[test]
+    fn test_unsupported_hole_punch_is_not_an_error() {
+        // What ramfs returns; ENOTSUP is the same value on Linux
+        assert!(punched(Err(Errno::EOPNOTSUPP)).is_ok());
+        assert!(punched(Err(Errno::ENOTSUP)).is_ok());
+        assert!(punched(Err(Errno::EIO)).is_err());
+    }
+}
@@ -1,34 +1,136 @@
+pub mod compression;
 pub mod cow;
 pub mod detector;
+pub mod encryption;
+pub mod f2fs;
+pub mod fat;
+pub mod fiemap;
+pub mod flags;
+pub mod journal;
+pub mod memory;
+pub mod mountinfo;
+pub mod overlay;
+pub mod shared;
+pub mod snapshots;
 
-use crate::Result;
-use std::path::Path;
+use crate::{NotWipeable, Result};
+use mountinfo::{MountEntry, MountOptions};
+use std::fs::File;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone)]
 pub enum FilesystemType {
-    Ext4 { has_journal: bool },
-    Btrfs { subvolume: bool },
-    Xfs { realtime: bool },
-    Zfs { compression: bool },
+    Ext4 {
+        has_journal: bool,
+        data_journal: bool,
+    },
+    Btrfs {
+        subvolume: bool,
+        compression: Option<String>,
+        nodatacow: bool,
+    },
+    Xfs {
+        realtime: bool,
+    },
+    Zfs {
+        compression: bool,
+        dedup: bool,
+    },
     F2fs,
+    /// vfat, or msdos when `long_names` is false
+    Fat {
+        long_names: bool,
+    },
+    Exfat,
+    /// The in-kernel ntfs3 driver
+    Ntfs,
+    Overlay {
+        upperdir: Option<PathBuf>,
+        lowerdirs: Vec<PathBuf>,
+    },
+    /// eCryptfs stacked on the directory holding the ciphertext
+    Ecryptfs {
+        lower: Option<PathBuf>,
+    },
+    /// RAM-backed; tmpfs pages can be swapped out, ramfs pages can't
+    Tmpfs,
+    Ramfs,
+    /// NFS, SMB and other filesystems whose blocks live on a server
+    Network {
+        protocol: String,
+    },
+    /// Userspace filesystems (sshfs, rclone, ...); the daemon decides
+    /// where data ends up
+    Fuse {
+        subtype: String,
+    },
     Unknown,
 }
 
+/// A detected filesystem and the mount it was reached through
+#[derive(Debug, Clone)]
+pub struct FilesystemInfo {
+    pub fs_type: FilesystemType,
+    pub mount: Option<MountEntry>,
+    pub options: MountOptions,
+}
+
+impl FilesystemInfo {
+    pub fn unknown() -> Self {
+        Self {
+            fs_type: FilesystemType::Unknown,
+            mount: None,
+            options: MountOptions::default(),
+        }
+    }
+
+    pub fn get_optimizer(&self) -> Box<dyn FilesystemOptimizer> {
+        self.fs_type.get_optimizer(&self.options)
+    }
+}
+
 pub trait FilesystemOptimizer {
     fn pre_wipe_setup(&self, path: &Path) -> Result<()>;
-    fn post_wipe_cleanup(&self, path: &Path) -> Result<()>;
+    fn post_wipe_cleanup(&self, file: &File) -> Result<()>;
     fn get_recommended_passes(&self) -> usize;
     fn should_disable_cow(&self) -> bool;
+
+    /// Make overwrites of `file` land in place where the filesystem allows it
+    fn disable_cow(&self, _file: &File) -> cow::CowStatus {
+        cow::CowStatus::NotApplicable
+    }
+
+    /// Ask the filesystem to erase the file's current blocks on the device,
+    /// after the overwrite passes
+    fn trim_file(&self, _file: &File) -> f2fs::TrimStatus {
+        f2fs::TrimStatus::NotApplicable
+    }
+
+    /// How renames before unlinking must form names so they overwrite the
+    /// original directory entries; `None` uses the generic shrinking names
+    fn name_rules(&self) -> Option<fat::NameRules> {
+        None
+    }
 }
 
-pub struct DefaultOptimizer;
+/// Refuse to start on a read-only mount instead of failing on the first write
+pub(crate) fn ensure_writable(options: &MountOptions, path: &Path) -> Result<()> {
+    if options.read_only {
+        return Err(NotWipeable::new(path, "filesystem is mounted read-only").into());
+    }
+    Ok(())
+}
+
+pub struct DefaultOptimizer {
+    pub options: MountOptions,
+}
 
 impl FilesystemOptimizer for DefaultOptimizer {
-    fn pre_wipe_setup(&self, _path: &Path) -> Result<()> {
-        Ok(())
+    fn pre_wipe_setup(&self, path: &Path) -> Result<()> {
+        ensure_writable(&self.options, path)
     }
 
-    fn post_wipe_cleanup(&self, _path: &Path) -> Result<()> {
+    fn post_wipe_cleanup(&self, _file: &File) -> Result<()> {
         Ok(())
     }
 
@@ -42,27 +144,62 @@ impl FilesystemOptimizer for DefaultOptimizer {
 }
 
 impl FilesystemType {
-    pub fn get_optimizer(&self) -> Box<dyn FilesystemOptimizer> {
+    pub fn get_optimizer(&self, options: &MountOptions) -> Box<dyn FilesystemOptimizer> {
+        let options = options.clone();
         match self {
-            FilesystemType::Btrfs { .. } => Box::new(cow::BtrfsOptimizer),
-            FilesystemType::Zfs { .. } => Box::new(cow::ZfsOptimizer),
-            FilesystemType::Ext4 { .. } => Box::new(detector::Ext4Optimizer),
-            FilesystemType::Xfs { .. } => Box::new(detector::XfsOptimizer),
-            FilesystemType::F2fs => Box::new(detector::F2fsOptimizer),
-            FilesystemType::Unknown => Box::new(DefaultOptimizer),
+            FilesystemType::Btrfs { .. } => Box::new(cow::BtrfsOptimizer { options }),
+            FilesystemType::Zfs { .. } => Box::new(cow::ZfsOptimizer { options }),
+            FilesystemType::Ext4 { has_journal, .. } => Box::new(detector::Ext4Optimizer {
+                options,
+                has_journal: *has_journal,
+            }),
+            FilesystemType::Xfs { .. } => Box::new(detector::XfsOptimizer { options }),
+            FilesystemType::F2fs => Box::new(detector::F2fsOptimizer { options }),
+            FilesystemType::Fat { long_names } => Box::new(fat::FatOptimizer {
+                options,
+                long_names: *long_names,
+            }),
+            FilesystemType::Exfat => Box::new(fat::ExfatOptimizer { options }),
+            FilesystemType::Ntfs => Box::new(fat::NtfsOptimizer { options }),
+            FilesystemType::Tmpfs => Box::new(memory::MemoryOptimizer {
+                options,
+                swappable: true,
+            }),
+            FilesystemType::Ramfs => Box::new(memory::MemoryOptimizer {
+                options,
+                swappable: false,
+            }),
+            FilesystemType::Overlay { .. }
+            | FilesystemType::Ecryptfs { .. }
+            | FilesystemType::Network { .. }
+            | FilesystemType::Fuse { .. }
+            | FilesystemType::Unknown => Box::new(DefaultOptimizer { options }),
         }
     }
 
     pub fn supports_cow(&self) -> bool {
+        match self {
+            FilesystemType::Btrfs { nodatacow, .. } => !*nodatacow,
+            FilesystemType::Zfs { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Whether overwriting the file here can't reach the storage holding it
+    pub fn is_remote(&self) -> bool {
         matches!(
             self,
-            FilesystemType::Btrfs { .. } | FilesystemType::Zfs { .. }
+            FilesystemType::Network { .. } | FilesystemType::Fuse { .. }
         )
     }
 
+    pub fn is_memory_backed(&self) -> bool {
+        matches!(self, FilesystemType::Tmpfs | FilesystemType::Ramfs)
+    }
+
     pub fn is_journaled(&self) -> bool {
         match self {
-            FilesystemType::Ext4 { has_journal } => *has_journal,
+            FilesystemType::Ext4 { has_journal, .. } => *has_journal,
             FilesystemType::Xfs { .. } => true,
             FilesystemType::F2fs => true,
             _ => false,
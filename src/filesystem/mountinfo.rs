@@ -0,0 +1,275 @@
+use crate::sysroot::SysRoot;
+use crate::Result;
+use anyhow::Context;
+use std::fs;
+use std::path::PathBuf;
+
+/// One line of `/proc/<pid>/mountinfo` (see proc(5))
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountEntry {
+    pub mount_id: u32,
+    pub parent_id: u32,
+    pub major: u64,
+    pub minor: u64,
+    /// Directory within the filesystem that forms the root of this mount
+    /// (e.g. `/@home` for a btrfs subvolume, or a bind-mounted subtree)
+    pub root: PathBuf,
+    pub mount_point: PathBuf,
+    /// Per-mount options (`ro`, `noatime`, ...)
+    pub mount_options: Vec<String>,
+    pub fs_type: String,
+    pub source: String,
+    /// Per-superblock options (`data=journal`, `compress=zstd:3`, ...)
+    pub super_options: Vec<String>,
+}
+
+/// Mount options that decide whether an in-place overwrite reaches the
+/// blocks that held the old data
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MountOptions {
+    pub read_only: bool,
+    pub noatime: bool,
+    /// Online discard: freed extents are trimmed as soon as they are released
+    pub discard: bool,
+    /// ext4 `data=journal`: file data is written to the journal before
+    /// its final location
+    pub data_journal: bool,
+    /// btrfs `compress=`/`compress-force=` algorithm, if compression is on
+    pub compression: Option<String>,
+    pub compress_force: bool,
+    /// btrfs `nodatacow`: data is overwritten in place
+    pub nodatacow: bool,
+    /// XFS realtime device holding file data
+    pub rtdev: Option<String>,
+    /// overlayfs read-only layers, topmost first
+    pub lowerdirs: Vec<String>,
+    /// overlayfs writable layer
+    pub upperdir: Option<String>,
+    /// eCryptfs content and filename key signatures
+    pub ecryptfs_sigs: Vec<String>,
+}
+
+impl MountOptions {
+    /// Combine per-mount and per-superblock options; a read-only flag on
+    /// either makes the mount read-only.
+    pub fn from_entry(entry: &MountEntry) -> Self {
+        let mut options = Self::default();
+
+        for option in entry.mount_options.iter().chain(&entry.super_options) {
+            let (key, value) = match option.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (option.as_str(), None),
+            };
+
+            match (key, value) {
+                ("ro", None) => options.read_only = true,
+                ("noatime", None) => options.noatime = true,
+                ("discard", _) => options.discard = true,
+                ("nodiscard", None) => options.discard = false,
+                ("data", Some("journal")) => options.data_journal = true,
+                ("compress", Some(algorithm)) | ("compress-force", Some(algorithm)) => {
+                    options.compression = (algorithm != "no").then(|| algorithm.to_string());
+                    options.compress_force = key == "compress-force" && algorithm != "no";
+                }
+                ("compress", None) | ("compress-force", None) => {
+                    // Bare compress means zlib
+                    options.compression = Some("zlib".to_string());
+                    options.compress_force = key == "compress-force";
+                }
+                ("nodatacow", None) => options.nodatacow = true,
+                ("datacow", None) => options.nodatacow = false,
+                ("rtdev", Some(device)) => options.rtdev = Some(device.to_string()),
+                ("lowerdir", Some(dirs)) => options.lowerdirs.extend(split_layers(dirs)),
+                // Layers added one at a time through the new mount API;
+                // data-only layers still hold file contents
+                ("lowerdir+", Some(dir)) | ("datadir+", Some(dir)) => {
+                    options.lowerdirs.push(dir.to_string())
+                }
+                ("upperdir", Some(dir)) => options.upperdir = Some(dir.to_string()),
+                ("ecryptfs_sig", Some(sig)) | ("ecryptfs_fnek_sig", Some(sig)) => {
+                    options.ecryptfs_sigs.push(sig.to_string())
+                }
+                _ => {}
+            }
+        }
+
+        options
+    }
+}
+
+/// Split an overlayfs `lowerdir=` list on `:`, honouring `\:` escapes. A
+/// `::` separates data-only layers and yields no empty entry.
+fn split_layers(dirs: &str) -> Vec<String> {
+    let mut layers = Vec::new();
+    let mut current = String::new();
+    let mut chars = dirs.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => current.extend(chars.next()),
+            ':' => {
+                if !current.is_empty() {
+                    layers.push(std::mem::take(&mut current));
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        layers.push(current);
+    }
+
+    layers
+}
+
+pub fn read_mountinfo(root: &SysRoot) -> Result<Vec<MountEntry>> {
+    let path = root.proc("self/mountinfo");
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(parse_mountinfo(&content))
+}
+
+/// Parse mountinfo content, skipping malformed lines
+pub fn parse_mountinfo(content: &str) -> Vec<MountEntry> {
+    content.lines().filter_map(parse_mountinfo_line).collect()
+}
+
+fn parse_mountinfo_line(line: &str) -> Option<MountEntry> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+
+    // Optional fields (shared:N, master:N, ...) end at a lone "-"
+    let separator = fields.iter().position(|field| *field == "-")?;
+    if separator < 6 || fields.len() < separator + 3 {
+        return None;
+    }
+
+    let (major, minor) = fields[2].split_once(':')?;
+
+    Some(MountEntry {
+        mount_id: fields[0].parse().ok()?,
+        parent_id: fields[1].parse().ok()?,
+        major: major.parse().ok()?,
+        minor: minor.parse().ok()?,
+        root: PathBuf::from(unescape(fields[3])),
+        mount_point: PathBuf::from(unescape(fields[4])),
+        mount_options: split_options(fields[5]),
+        fs_type: fields[separator + 1].to_string(),
+        source: unescape(fields[separator + 2]),
+        super_options: fields
+            .get(separator + 3)
+            .map(|options| split_options(options))
+            .unwrap_or_default(),
+    })
+}
+
+fn split_options(options: &str) -> Vec<String> {
+    options
+        .split(',')
+        .filter(|option| !option.is_empty())
+        .map(unescape)
+        .collect()
+}
+
+/// The kernel escapes space, tab, newline and backslash as `\ooo`
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            let octal = std::str::from_utf8(&bytes[i + 1..i + 4]).ok();
+            if let Some(byte) = octal.and_then(|octal| u8::from_str_radix(octal, 8).ok()) {
+                out.push(byte);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mountinfo_line() {
+        let entries = parse_mountinfo(
+            "36 35 98:0 /mnt1 /mnt/my\\040disk rw,noatime master:1 - ext3 /dev/root rw,errors=continue\n\
+             garbage line\n\
+             22 1 254:0 /@ / rw,relatime shared:1 - btrfs /dev/mapper/root rw,compress=zstd:3,subvol=/@",
+        );
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].mount_id, 36);
+        assert_eq!(entries[0].parent_id, 35);
+        assert_eq!((entries[0].major, entries[0].minor), (98, 0));
+        assert_eq!(entries[0].root, PathBuf::from("/mnt1"));
+        assert_eq!(entries[0].mount_point, PathBuf::from("/mnt/my disk"));
+        assert_eq!(entries[0].mount_options, ["rw", "noatime"]);
+        assert_eq!(entries[0].fs_type, "ext3");
+        assert_eq!(entries[0].source, "/dev/root");
+        assert_eq!(entries[0].super_options, ["rw", "errors=continue"]);
+        assert_eq!(entries[1].super_options[1], "compress=zstd:3");
+    }
+
+    #[test]
+    fn test_overlay_layers() {
+        let entries = parse_mountinfo(
+            "30 1 0:45 / /merged rw - overlay overlay rw,lowerdir=/l1:/l\\:2::/data,upperdir=/up,workdir=/work\n\
+             31 1 0:46 / /new rw - overlay overlay rw,lowerdir+=/a,lowerdir+=/b,datadir+=/d,upperdir=/u",
+        );
+
+        let classic = MountOptions::from_entry(&entries[0]);
+        assert_eq!(classic.lowerdirs, ["/l1", "/l:2", "/data"]);
+        assert_eq!(classic.upperdir.as_deref(), Some("/up"));
+
+        let new_api = MountOptions::from_entry(&entries[1]);
+        assert_eq!(new_api.lowerdirs, ["/a", "/b", "/d"]);
+        assert_eq!(new_api.upperdir.as_deref(), Some("/u"));
+    }
+
+    #[test]
+    fn test_ecryptfs_signatures() {
+        let entries = parse_mountinfo(
+            "40 30 0:50 / /home/alice rw - ecryptfs /home/.ecryptfs/alice/.Private rw,ecryptfs_fnek_sig=7f1b5c2e0a9d3e41,ecryptfs_sig=d395309aaad4de06,ecryptfs_cipher=aes",
+        );
+        let options = MountOptions::from_entry(&entries[0]);
+        assert_eq!(
+            options.ecryptfs_sigs,
+            ["7f1b5c2e0a9d3e41", "d395309aaad4de06"]
+        );
+    }
+
+    #[test]
+    fn test_mount_options() {
+        let entries = parse_mountinfo(
+            "22 1 254:0 /@ / ro,noatime - btrfs /dev/mapper/root rw,compress-force=zstd:3,nodatacow,discard=async\n\
+             23 1 8:2 / /data rw - ext4 /dev/sda2 rw,data=journal\n\
+             24 1 8:3 / /rt rw - xfs /dev/sda3 rw,rtdev=/dev/sdb1\n\
+             25 1 254:0 /@ /nc rw - btrfs /dev/mapper/root rw,compress=no",
+        );
+
+        let btrfs = MountOptions::from_entry(&entries[0]);
+        assert!(btrfs.read_only);
+        assert!(btrfs.noatime);
+        assert!(btrfs.discard);
+        assert!(btrfs.nodatacow);
+        assert!(btrfs.compress_force);
+        assert_eq!(btrfs.compression.as_deref(), Some("zstd:3"));
+
+        let ext4 = MountOptions::from_entry(&entries[1]);
+        assert!(ext4.data_journal);
+        assert!(!ext4.read_only);
+
+        let xfs = MountOptions::from_entry(&entries[2]);
+        assert_eq!(xfs.rtdev.as_deref(), Some("/dev/sdb1"));
+
+        let uncompressed = MountOptions::from_entry(&entries[3]);
+        assert!(uncompressed.compression.is_none());
+    }
+}
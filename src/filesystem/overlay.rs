@@ -0,0 +1,139 @@
+use super::{FilesystemInfo, FilesystemType};
+use crate::sysroot::SysRoot;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where an overlayfs file's data lives in the underlying layers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverlayCopy {
+    /// The file has a copy in the upper layer, which is what a wipe through
+    /// the overlay overwrites. `lower` holds originals it was copied up from.
+    CopiedUp { upper: PathBuf, lower: Vec<PathBuf> },
+    /// Only read-only layers hold the file. Writing to it would first copy
+    /// the data up, and deleting it only adds a whiteout.
+    LowerOnly { lower: Vec<PathBuf> },
+    /// The layer directories can't be inspected from here (usually inside
+    /// an unprivileged container)
+    Unknown(String),
+}
+
+/// Look up `path` (canonical, below the overlay mount) in each layer
+pub fn locate_layers(
+    root: &SysRoot,
+    path: &Path,
+    filesystem: &FilesystemInfo,
+) -> Option<OverlayCopy> {
+    let FilesystemType::Overlay {
+        upperdir,
+        lowerdirs,
+    } = &filesystem.fs_type
+    else {
+        return None;
+    };
+    let mount = filesystem.mount.as_ref()?;
+    let relative = path.strip_prefix(&mount.mount_point).ok()?;
+
+    let inaccessible = upperdir
+        .iter()
+        .chain(lowerdirs)
+        .find(|dir| fs::metadata(root.path(dir)).is_err());
+    if let Some(dir) = inaccessible {
+        return Some(OverlayCopy::Unknown(format!(
+            "layer {} is not accessible",
+            dir.display()
+        )));
+    }
+    if lowerdirs.is_empty() {
+        return Some(OverlayCopy::Unknown(
+            "mountinfo does not list the lower layers".to_string(),
+        ));
+    }
+
+    let exists = |dir: &PathBuf| {
+        let candidate = dir.join(relative);
+        fs::symlink_metadata(root.path(&candidate))
+            .is_ok()
+            .then_some(candidate)
+    };
+    let lower: Vec<PathBuf> = lowerdirs.iter().filter_map(exists).collect();
+
+    Some(match upperdir.as_ref().and_then(exists) {
+        Some(upper) => OverlayCopy::CopiedUp { upper, lower },
+        None => OverlayCopy::LowerOnly { lower },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::mountinfo::{parse_mountinfo, MountOptions};
+    use tempfile::tempdir;
+
+    fn overlay(dir: &Path) -> FilesystemInfo {
+        let mount = parse_mountinfo(
+            "30 1 0:45 / /merged rw - overlay overlay rw,lowerdir=/layers/l1:/layers/l2,upperdir=/layers/up,workdir=/layers/work",
+        )
+        .remove(0);
+        let options = MountOptions::from_entry(&mount);
+        for layer in ["layers/l1/etc", "layers/l2/etc", "layers/up/etc", "merged"] {
+            fs::create_dir_all(dir.join(layer)).unwrap();
+        }
+
+        FilesystemInfo {
+            fs_type: FilesystemType::Overlay {
+                upperdir: options.upperdir.as_ref().map(PathBuf::from),
+                lowerdirs: options.lowerdirs.iter().map(PathBuf::from).collect(),
+            },
+            mount: Some(mount),
+            options,
+        }
+    }
+
+    #[test]
+    fn test_copied_up_and_lower_only() {
+        let dir = tempdir().unwrap();
+        let root = SysRoot::new(dir.path());
+        let filesystem = overlay(dir.path());
+        fs::write(dir.path().join("layers/l2/etc/shadow"), "orig").unwrap();
+        fs::write(dir.path().join("layers/up/etc/shadow"), "edited").unwrap();
+        fs::write(dir.path().join("layers/l1/etc/key"), "key").unwrap();
+        fs::write(dir.path().join("layers/up/etc/new"), "new").unwrap();
+
+        assert_eq!(
+            locate_layers(&root, Path::new("/merged/etc/shadow"), &filesystem),
+            Some(OverlayCopy::CopiedUp {
+                upper: PathBuf::from("/layers/up/etc/shadow"),
+                lower: vec![PathBuf::from("/layers/l2/etc/shadow")],
+            })
+        );
+        assert_eq!(
+            locate_layers(&root, Path::new("/merged/etc/key"), &filesystem),
+            Some(OverlayCopy::LowerOnly {
+                lower: vec![PathBuf::from("/layers/l1/etc/key")],
+            })
+        );
+        assert_eq!(
+            locate_layers(&root, Path::new("/merged/etc/new"), &filesystem),
+            Some(OverlayCopy::CopiedUp {
+                upper: PathBuf::from("/layers/up/etc/new"),
+                lower: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_inaccessible_layers() {
+        let dir = tempdir().unwrap();
+        let filesystem = overlay(dir.path());
+        fs::remove_dir_all(dir.path().join("layers/up")).unwrap();
+
+        assert!(matches!(
+            locate_layers(
+                &SysRoot::new(dir.path()),
+                Path::new("/merged/etc/shadow"),
+                &filesystem
+            ),
+            Some(OverlayCopy::Unknown(_))
+        ));
+    }
+}
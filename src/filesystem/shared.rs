@@ -0,0 +1,198 @@
+use super::fiemap::Extent;
+use super::FilesystemType;
+use nix::{ioctl_readwrite, ioctl_readwrite_bad, request_code_readwrite};
+use std::fs::File;
+use std::mem;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+
+pub(crate) const BTRFS_IOCTL_MAGIC: u8 = 0x94;
+const BTRFS_FIRST_FREE_OBJECTID: u64 = 256;
+const LOGICAL_INO_BUFFER: usize = 64 * 1024;
+/// Stop asking the kernel once this many other owners are known
+const MAX_SHARERS: usize = 32;
+
+#[repr(C)]
+struct BtrfsInoLookupArgs {
+    treeid: u64,
+    objectid: u64,
+    name: [u8; 4080],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct BtrfsLogicalInoArgs {
+    logical: u64,
+    size: u64,
+    reserved: [u64; 3],
+    flags: u64,
+    inodes: u64,
+}
+
+ioctl_readwrite!(
+    btrfs_ioc_ino_lookup,
+    BTRFS_IOCTL_MAGIC,
+    18,
+    BtrfsInoLookupArgs
+);
+// Declared as BTRFS_IOC_LOGICAL_INO in linux/btrfs.h
+ioctl_readwrite_bad!(
+    btrfs_ioc_logical_ino,
+    request_code_readwrite!(BTRFS_IOCTL_MAGIC, 36, mem::size_of::<BtrfsLogicalInoArgs>()),
+    BtrfsLogicalInoArgs
+);
+
+/// Another inode referencing one of the file's extents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Sharer {
+    /// btrfs subvolume (tree) id
+    pub root: u64,
+    pub inode: u64,
+}
+
+/// Extents the file shares with reflinked copies, dedupe or snapshots
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SharedExtents {
+    pub extents: usize,
+    pub bytes: u64,
+    /// Other owners, when the filesystem can tell us (btrfs, as root)
+    pub sharers: Vec<Sharer>,
+}
+
+/// Summarise the shared extents in `extents`, or `None` if nothing is shared
+pub fn find_shared(
+    file: &File,
+    extents: &[Extent],
+    fs_type: &FilesystemType,
+) -> Option<SharedExtents> {
+    let shared: Vec<&Extent> = extents.iter().filter(|e| e.is_shared()).collect();
+    if shared.is_empty() {
+        return None;
+    }
+
+    let sharers = match fs_type {
+        FilesystemType::Btrfs { .. } => btrfs_sharers(file, &shared),
+        _ => Vec::new(),
+    };
+
+    Some(SharedExtents {
+        extents: shared.len(),
+        bytes: shared.iter().map(|e| e.length).sum(),
+        sharers,
+    })
+}
+
+/// Resolve each extent back to the inodes referencing it. Needs
+/// CAP_SYS_ADMIN; without it the list is simply empty.
+fn btrfs_sharers(file: &File, shared: &[&Extent]) -> Vec<Sharer> {
+    let Ok(own_inode) = file.metadata().map(|m| m.ino()) else {
+        return Vec::new();
+    };
+    let Some(own_root) = btrfs_root_id(file) else {
+        return Vec::new();
+    };
+
+    let mut sharers = Vec::new();
+    let mut buffer = vec![0u64; LOGICAL_INO_BUFFER / mem::size_of::<u64>()];
+
+    for extent in shared {
+        let mut args = BtrfsLogicalInoArgs {
+            // FIEMAP reports btrfs logical addresses as "physical"
+            logical: extent.physical,
+            size: LOGICAL_INO_BUFFER as u64,
+            inodes: buffer.as_mut_ptr() as u64,
+            ..BtrfsLogicalInoArgs::default()
+        };
+        if unsafe { btrfs_ioc_logical_ino(file.as_raw_fd(), &mut args) }.is_err() {
+            break;
+        }
+
+        // struct btrfs_data_container: four u32 counters (elem_cnt is the
+        // third), then (inode, offset, root) triples
+        let elements = unsafe { buffer.as_ptr().cast::<u32>().add(2).read() } as usize;
+        let values = &buffer[2..2 + elements.min(buffer.len() - 2)];
+        for triple in values.chunks_exact(3) {
+            let sharer = Sharer {
+                inode: triple[0],
+                root: triple[2],
+            };
+            if (sharer.root, sharer.inode) != (own_root, own_inode) && !sharers.contains(&sharer) {
+                sharers.push(sharer);
+            }
+        }
+
+        if sharers.len() >= MAX_SHARERS {
+            break;
+        }
+    }
+
+    sharers.sort_unstable();
+    sharers
+}
+
+/// Id of the subvolume holding `file`
+pub(crate) fn btrfs_root_id(file: &File) -> Option<u64> {
+    btrfs_ino_lookup(file, 0, BTRFS_FIRST_FREE_OBJECTID).map(|(treeid, _)| treeid)
+}
+
+/// Path of directory `objectid` within subvolume `treeid` (0: the one
+/// holding `file`), with a trailing slash unless it is the subvolume's
+/// root. Looking up other subvolumes needs CAP_SYS_ADMIN.
+pub(crate) fn btrfs_ino_lookup(file: &File, treeid: u64, objectid: u64) -> Option<(u64, String)> {
+    let mut args = BtrfsInoLookupArgs {
+        treeid,
+        objectid,
+        name: [0; 4080],
+    };
+    unsafe { btrfs_ioc_ino_lookup(file.as_raw_fd(), &mut args) }.ok()?;
+    let len = args
+        .name
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(args.name.len());
+    Some((
+        args.treeid,
+        String::from_utf8_lossy(&args.name[..len]).into_owned(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::fiemap::FIEMAP_EXTENT_SHARED;
+    use tempfile::NamedTempFile;
+
+    fn extent(length: u64, flags: u32) -> Extent {
+        Extent {
+            logical: 0,
+            physical: 1 << 20,
+            length,
+            flags,
+        }
+    }
+
+    #[test]
+    fn test_find_shared() {
+        let file = NamedTempFile::new().unwrap();
+        let fs_type = FilesystemType::Xfs { realtime: false };
+
+        assert_eq!(
+            find_shared(file.as_file(), &[extent(4096, 0)], &fs_type),
+            None
+        );
+
+        let extents = [
+            extent(4096, FIEMAP_EXTENT_SHARED),
+            extent(8192, 0),
+            extent(65536, FIEMAP_EXTENT_SHARED),
+        ];
+        assert_eq!(
+            find_shared(file.as_file(), &extents, &fs_type),
+            Some(SharedExtents {
+                extents: 2,
+                bytes: 69632,
+                sharers: Vec::new(),
+            })
+        );
+    }
+}
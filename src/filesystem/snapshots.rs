@@ -0,0 +1,739 @@
+use super::mountinfo::{read_mountinfo, MountEntry};
+use super::shared::{self, BTRFS_IOCTL_MAGIC};
+use super::{FilesystemInfo, FilesystemType};
+use crate::sysroot::SysRoot;
+use crate::Result;
+use anyhow::{bail, Context};
+use nix::errno::Errno;
+use nix::ioctl_readwrite;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+const TIMESHIFT_RSYNC_DIRS: [&str; 2] = [
+    "/timeshift/snapshots",
+    "/run/timeshift/backup/timeshift/snapshots",
+];
+const TIMESHIFT_BTRFS_DIR: &str = "/run/timeshift/backup/timeshift-btrfs/snapshots";
+
+const BTRFS_ROOT_TREE_OBJECTID: u64 = 1;
+const BTRFS_FS_TREE_OBJECTID: u64 = 5;
+const BTRFS_LAST_FREE_OBJECTID: u64 = -256i64 as u64;
+const BTRFS_ROOT_ITEM_KEY: u32 = 132;
+const BTRFS_ROOT_BACKREF_KEY: u32 = 144;
+/// Offsets of `uuid` and `parent_uuid` in struct btrfs_root_item
+const ROOT_ITEM_UUID: usize = 247;
+const ROOT_ITEM_PARENT_UUID: usize = 263;
+/// struct btrfs_ioctl_search_header
+const SEARCH_HEADER_SIZE: usize = 32;
+
+/// struct btrfs_ioctl_search_key
+#[repr(C)]
+#[derive(Default)]
+struct BtrfsSearchKey {
+    tree_id: u64,
+    min_objectid: u64,
+    max_objectid: u64,
+    min_offset: u64,
+    max_offset: u64,
+    min_transid: u64,
+    max_transid: u64,
+    min_type: u32,
+    max_type: u32,
+    nr_items: u32,
+    unused: u32,
+    unused1: u64,
+    unused2: u64,
+    unused3: u64,
+    unused4: u64,
+}
+
+/// struct btrfs_ioctl_search_args: the key, then results filling the
+/// rest of 4 KiB
+#[repr(C)]
+struct BtrfsSearchArgs {
+    key: BtrfsSearchKey,
+    buf: [u8; 4096 - std::mem::size_of::<BtrfsSearchKey>()],
+}
+
+const _: () = assert!(std::mem::size_of::<BtrfsSearchArgs>() == 4096);
+
+ioctl_readwrite!(
+    btrfs_ioc_tree_search,
+    BTRFS_IOCTL_MAGIC,
+    17,
+    BtrfsSearchArgs
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotKind {
+    Zfs,
+    Snapper,
+    Timeshift,
+    Btrfs,
+}
+
+impl fmt::Display for SnapshotKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotKind::Zfs => write!(f, "ZFS"),
+            SnapshotKind::Snapper => write!(f, "snapper"),
+            SnapshotKind::Timeshift => write!(f, "Timeshift"),
+            SnapshotKind::Btrfs => write!(f, "btrfs"),
+        }
+    }
+}
+
+/// A copy of the target preserved in a snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotCopy {
+    pub kind: SnapshotKind,
+    /// What the snapshot tool calls it: `pool/dataset@snap`, a snapper
+    /// number, a Timeshift tag or a btrfs subvolume path
+    pub name: String,
+    pub path: PathBuf,
+    /// Same inode number as the target (ZFS and btrfs keep them)
+    pub same_inode: bool,
+    pub same_content: bool,
+}
+
+impl SnapshotCopy {
+    /// Command that deletes the snapshot holding this copy
+    pub fn removal_hint(&self) -> String {
+        match self.kind {
+            SnapshotKind::Zfs => format!("zfs destroy {}", self.name),
+            SnapshotKind::Snapper => format!("snapper delete {}", self.name),
+            SnapshotKind::Timeshift => format!("timeshift --delete --snapshot '{}'", self.name),
+            SnapshotKind::Btrfs => format!("btrfs subvolume delete {}", self.name),
+        }
+    }
+}
+
+/// Copies found, and the snapshots that could not be searched
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotSearch {
+    pub copies: Vec<SnapshotCopy>,
+    /// Why some snapshots were not looked at; copies may hide there
+    pub unchecked: Vec<String>,
+}
+
+/// A btrfs subvolume as recorded in the root tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BtrfsSubvolume {
+    pub id: u64,
+    /// Relative to the top-level subvolume, which has an empty path
+    pub path: String,
+    pub uuid: [u8; 16],
+    /// UUID of the subvolume this is a snapshot of; all zero if none
+    pub parent_uuid: [u8; 16],
+}
+
+/// The subvolumes of one btrfs filesystem and the id of the one holding
+/// the target
+#[derive(Debug, Clone)]
+pub struct BtrfsListing {
+    pub subvolumes: Vec<BtrfsSubvolume>,
+    pub source: u64,
+}
+
+impl BtrfsListing {
+    /// Snapshots taken of the source subvolume, and snapshots of those
+    pub fn snapshots_of_source(&self) -> Option<Vec<&BtrfsSubvolume>> {
+        let source = self.subvolumes.iter().find(|s| s.id == self.source)?;
+        if source.uuid == [0; 16] {
+            return None;
+        }
+
+        let mut origins = vec![source.uuid];
+        let mut snapshots: Vec<&BtrfsSubvolume> = Vec::new();
+        while let Some(origin) = origins.pop() {
+            for subvolume in &self.subvolumes {
+                if subvolume.parent_uuid == origin
+                    && !snapshots.iter().any(|known| known.id == subvolume.id)
+                {
+                    snapshots.push(subvolume);
+                    origins.push(subvolume.uuid);
+                }
+            }
+        }
+        snapshots.sort_by(|a, b| a.path.cmp(&b.path));
+        Some(snapshots)
+    }
+
+    fn source(&self) -> Option<&BtrfsSubvolume> {
+        self.subvolumes.iter().find(|s| s.id == self.source)
+    }
+}
+
+/// Target as it is before the wipe, to compare snapshot copies against
+struct Target {
+    inode: u64,
+    size: u64,
+    hash: Option<blake3::Hash>,
+}
+
+/// Look for copies of `path` (canonical, as seen by `root`) in the
+/// snapshots that the filesystem and the usual snapshot tools keep
+pub fn find_snapshot_copies(
+    root: &SysRoot,
+    path: &Path,
+    filesystem: &FilesystemInfo,
+) -> SnapshotSearch {
+    search_snapshots(root, path, filesystem, || {
+        list_btrfs_filesystem(&root.path(path))
+    })
+}
+
+fn search_snapshots(
+    root: &SysRoot,
+    path: &Path,
+    filesystem: &FilesystemInfo,
+    list_btrfs: impl FnOnce() -> Result<BtrfsListing>,
+) -> SnapshotSearch {
+    let mut search = SnapshotSearch::default();
+    let Some(mount) = &filesystem.mount else {
+        return search;
+    };
+    let Ok(relative) = path.strip_prefix(&mount.mount_point) else {
+        return search;
+    };
+    let Ok(metadata) = fs::metadata(root.path(path)) else {
+        return search;
+    };
+    // (kind, name, absolute path of the candidate copy)
+    let mut candidates: Vec<(SnapshotKind, String, PathBuf)> = Vec::new();
+
+    if let FilesystemType::Zfs { .. } = filesystem.fs_type {
+        for snapshot in list_dir(root, &mount.mount_point.join(".zfs/snapshot")) {
+            let name = file_name(&snapshot);
+            candidates.push((
+                SnapshotKind::Zfs,
+                format!("{}@{}", mount.source, name),
+                snapshot.join(relative),
+            ));
+        }
+    }
+
+    for snapshot in list_dir(root, &mount.mount_point.join(".snapshots")) {
+        candidates.push((
+            SnapshotKind::Snapper,
+            file_name(&snapshot),
+            snapshot.join("snapshot").join(relative),
+        ));
+    }
+
+    let absolute = path.strip_prefix("/").unwrap_or(path);
+    for dir in TIMESHIFT_RSYNC_DIRS {
+        for snapshot in list_dir(root, Path::new(dir)) {
+            candidates.push((
+                SnapshotKind::Timeshift,
+                file_name(&snapshot),
+                snapshot.join("localhost").join(absolute),
+            ));
+        }
+    }
+
+    if let FilesystemType::Btrfs { .. } = filesystem.fs_type {
+        // Timeshift names the snapshot after the subvolume, e.g. `@` or `@home`
+        let subvolume = mount.root.to_string_lossy().trim_matches('/').to_string();
+        if !subvolume.is_empty() {
+            for snapshot in list_dir(root, Path::new(TIMESHIFT_BTRFS_DIR)) {
+                candidates.push((
+                    SnapshotKind::Timeshift,
+                    file_name(&snapshot),
+                    snapshot.join(&subvolume).join(relative),
+                ));
+            }
+        }
+
+        match list_btrfs() {
+            Ok(listing) => {
+                let mounts = read_mountinfo(root).unwrap_or_default();
+                match btrfs_candidates(&listing, &mounts, mount, relative) {
+                    Some(found) => candidates.extend(found),
+                    None => search.unchecked.push(
+                        "btrfs snapshots: cannot tell which subvolume the file is in".to_string(),
+                    ),
+                }
+            }
+            Err(e) => search.unchecked.push(format!("btrfs snapshots: {:#}", e)),
+        }
+    }
+
+    if candidates.is_empty() {
+        return search;
+    }
+    let target = Target {
+        inode: metadata.ino(),
+        size: metadata.len(),
+        hash: hash_file(&root.path(path)),
+    };
+
+    let copies = &mut search.copies;
+    let mut seen = Vec::new();
+    for (kind, name, candidate) in candidates {
+        let resolved = root.path(&candidate);
+        let Ok(metadata) = fs::metadata(&resolved) else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        // snapper's snapshots are btrfs subvolumes too; report each file once
+        if seen.contains(&(metadata.dev(), metadata.ino())) {
+            continue;
+        }
+        seen.push((metadata.dev(), metadata.ino()));
+
+        let same_content = metadata.len() == target.size
+            && target.hash.is_some()
+            && hash_file(&resolved) == target.hash;
+        copies.push(SnapshotCopy {
+            kind,
+            name,
+            path: candidate,
+            same_inode: metadata.ino() == target.inode,
+            same_content,
+        });
+    }
+
+    search
+}
+
+/// Where the file would be in each snapshot of its own subvolume. `None`
+/// if the file's subvolume is not in the listing or has no UUID.
+fn btrfs_candidates(
+    listing: &BtrfsListing,
+    mounts: &[MountEntry],
+    mount: &MountEntry,
+    relative: &Path,
+) -> Option<Vec<(SnapshotKind, String, PathBuf)>> {
+    // The file's path inside its own subvolume, which need not be the
+    // subvolume the mount starts at
+    let source = Path::new("/").join(&listing.source()?.path);
+    let within = mount.root.join(relative);
+    let within = within.strip_prefix(&source).ok()?;
+
+    let mut candidates = Vec::new();
+    for snapshot in listing.snapshots_of_source()? {
+        if let Some(location) = locate_subvolume(mounts, &mount.source, &snapshot.path) {
+            candidates.push((
+                SnapshotKind::Btrfs,
+                location.display().to_string(),
+                location.join(within),
+            ));
+        }
+    }
+    Some(candidates)
+}
+
+/// List the subvolumes of the btrfs filesystem holding `path`
+fn list_btrfs_filesystem(path: &Path) -> Result<BtrfsListing> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let source = shared::btrfs_root_id(&file).context("Failed to look up the file's subvolume")?;
+    Ok(BtrfsListing {
+        subvolumes: list_btrfs_subvolumes(&file)?,
+        source,
+    })
+}
+
+/// Read every subvolume's UUIDs and location from the root tree with
+/// BTRFS_IOC_TREE_SEARCH, the way `btrfs subvolume list` does. Needs
+/// CAP_SYS_ADMIN.
+pub fn list_btrfs_subvolumes(file: &File) -> Result<Vec<BtrfsSubvolume>> {
+    let mut args = Box::new(BtrfsSearchArgs {
+        key: BtrfsSearchKey {
+            tree_id: BTRFS_ROOT_TREE_OBJECTID,
+            min_objectid: BTRFS_FS_TREE_OBJECTID,
+            max_objectid: BTRFS_LAST_FREE_OBJECTID,
+            max_offset: u64::MAX,
+            max_transid: u64::MAX,
+            min_type: BTRFS_ROOT_ITEM_KEY,
+            max_type: BTRFS_ROOT_BACKREF_KEY,
+            ..BtrfsSearchKey::default()
+        },
+        buf: [0; 4096 - std::mem::size_of::<BtrfsSearchKey>()],
+    });
+    // (uuid, parent_uuid) and (parent id, directory, name) per subvolume
+    let mut uuids: BTreeMap<u64, ([u8; 16], [u8; 16])> = BTreeMap::new();
+    let mut backrefs: HashMap<u64, (u64, u64, String)> = HashMap::new();
+
+    loop {
+        args.key.nr_items = 4096;
+        match unsafe { btrfs_ioc_tree_search(file.as_raw_fd(), &mut *args) } {
+            Ok(_) => {}
+            Err(Errno::EPERM) => bail!("listing subvolumes needs root (CAP_SYS_ADMIN)"),
+            Err(e) => return Err(e).context("BTRFS_IOC_TREE_SEARCH failed"),
+        }
+        if args.key.nr_items == 0 {
+            break;
+        }
+
+        let mut last = (0, 0, 0);
+        let mut pos = 0;
+        for _ in 0..args.key.nr_items {
+            let Some(header) = args.buf.get(pos..pos + SEARCH_HEADER_SIZE) else {
+                break;
+            };
+            let objectid = le_u64(header, 8);
+            let offset = le_u64(header, 16);
+            let item_type = le_u32(header, 24);
+            let len = le_u32(header, 28) as usize;
+            let start = pos + SEARCH_HEADER_SIZE;
+            let Some(item) = args.buf.get(start..start + len) else {
+                break;
+            };
+
+            match item_type {
+                // Items from before 3.10 carry no UUIDs
+                BTRFS_ROOT_ITEM_KEY if item.len() >= ROOT_ITEM_PARENT_UUID + 16 => {
+                    uuids.insert(
+                        objectid,
+                        (
+                            uuid_at(item, ROOT_ITEM_UUID),
+                            uuid_at(item, ROOT_ITEM_PARENT_UUID),
+                        ),
+                    );
+                }
+                // struct btrfs_root_ref: dirid, sequence, name_len, name
+                BTRFS_ROOT_BACKREF_KEY if item.len() >= 18 => {
+                    let name_len = u16::from_le_bytes([item[16], item[17]]) as usize;
+                    let name = item.get(18..18 + name_len).unwrap_or_default();
+                    backrefs.insert(
+                        objectid,
+                        (
+                            offset,
+                            le_u64(item, 0),
+                            String::from_utf8_lossy(name).into_owned(),
+                        ),
+                    );
+                }
+                _ => {}
+            }
+
+            last = (objectid, item_type, offset);
+            pos = start + len;
+        }
+
+        // Carry on from just past the last key returned
+        let (objectid, item_type, offset) = last;
+        let key = &mut args.key;
+        if offset < u64::MAX {
+            (key.min_objectid, key.min_type, key.min_offset) = (objectid, item_type, offset + 1);
+        } else if item_type < u8::MAX as u32 {
+            (key.min_objectid, key.min_type, key.min_offset) = (objectid, item_type + 1, 0);
+        } else if objectid < BTRFS_LAST_FREE_OBJECTID {
+            (key.min_objectid, key.min_type, key.min_offset) = (objectid + 1, 0, 0);
+        } else {
+            break;
+        }
+    }
+
+    // Trees without a backref are internal ones or deleted subvolumes
+    Ok(uuids
+        .into_iter()
+        .filter_map(|(id, (uuid, parent_uuid))| {
+            Some(BtrfsSubvolume {
+                id,
+                path: subvolume_path(file, id, &backrefs, 0)?,
+                uuid,
+                parent_uuid,
+            })
+        })
+        .collect())
+}
+
+/// Path of subvolume `id` from the top level, following backrefs up
+fn subvolume_path(
+    file: &File,
+    id: u64,
+    backrefs: &HashMap<u64, (u64, u64, String)>,
+    depth: usize,
+) -> Option<String> {
+    if id == BTRFS_FS_TREE_OBJECTID {
+        return Some(String::new());
+    }
+    if depth > 64 {
+        return None;
+    }
+    let (parent, dirid, name) = backrefs.get(&id)?;
+    let parent_path = subvolume_path(file, *parent, backrefs, depth + 1)?;
+    let (_, dir) = shared::btrfs_ino_lookup(file, *parent, *dirid)?;
+    Some(if parent_path.is_empty() {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}{}", parent_path, dir, name)
+    })
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn uuid_at(bytes: &[u8], at: usize) -> [u8; 16] {
+    bytes[at..at + 16].try_into().unwrap()
+}
+
+/// Where a subvolume of the filesystem on `source` can be reached, going
+/// through any mount of that filesystem whose root contains it
+pub fn locate_subvolume(mounts: &[MountEntry], source: &str, subvolume: &str) -> Option<PathBuf> {
+    let subvolume = Path::new("/").join(subvolume);
+    mounts
+        .iter()
+        .filter(|mount| mount.source == source && mount.fs_type == "btrfs")
+        .filter_map(|mount| {
+            let rest = subvolume.strip_prefix(&mount.root).ok()?;
+            Some((
+                mount.root.components().count(),
+                mount.mount_point.join(rest),
+            ))
+        })
+        // The most specific mount gives the shortest path
+        .max_by_key(|(depth, _)| *depth)
+        .map(|(_, path)| path)
+}
+
+fn list_dir(root: &SysRoot, dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(root.path(dir)) else {
+        return Vec::new();
+    };
+    let mut snapshots: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| dir.join(entry.file_name()))
+        .collect();
+    snapshots.sort();
+    snapshots
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// BLAKE3 of the file's content, to compare snapshot copies with the target
+fn hash_file(path: &Path) -> Option<blake3::Hash> {
+    let mut file = File::open(path).ok()?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer).ok()?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Some(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::mountinfo::{parse_mountinfo, MountOptions};
+    use tempfile::tempdir;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path.trim_start_matches('/'));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn filesystem(fs_type: FilesystemType, mountinfo: &str) -> FilesystemInfo {
+        FilesystemInfo {
+            fs_type,
+            mount: parse_mountinfo(mountinfo).into_iter().next(),
+            options: MountOptions::default(),
+        }
+    }
+
+    #[test]
+    fn test_zfs_and_timeshift_copies() {
+        let dir = tempdir().unwrap();
+        let root = SysRoot::new(dir.path());
+        write(dir.path(), "/tank/home/notes.txt", "secret");
+        write(
+            dir.path(),
+            "/tank/home/.zfs/snapshot/daily/notes.txt",
+            "secret",
+        );
+        write(
+            dir.path(),
+            "/tank/home/.zfs/snapshot/weekly/notes.txt",
+            "older",
+        );
+        write(dir.path(), "/tank/home/.zfs/snapshot/empty/other.txt", "x");
+        write(
+            dir.path(),
+            "/timeshift/snapshots/2024-05-01_10-00-01/localhost/tank/home/notes.txt",
+            "secret",
+        );
+
+        let zfs = filesystem(
+            FilesystemType::Zfs {
+                compression: false,
+                dedup: false,
+            },
+            "50 1 0:50 / /tank/home rw - zfs tank/home rw\n",
+        );
+        let copies = find_snapshot_copies(&root, Path::new("/tank/home/notes.txt"), &zfs).copies;
+
+        assert_eq!(copies.len(), 3);
+        assert_eq!(copies[0].name, "tank/home@daily");
+        assert!(copies[0].same_content);
+        assert_eq!(copies[0].removal_hint(), "zfs destroy tank/home@daily");
+        assert_eq!(copies[1].name, "tank/home@weekly");
+        assert!(!copies[1].same_content);
+        assert_eq!(copies[2].kind, SnapshotKind::Timeshift);
+        assert!(copies[2].same_content);
+    }
+
+    #[test]
+    fn test_snapper_and_timeshift_btrfs_copies() {
+        let dir = tempdir().unwrap();
+        let root = SysRoot::new(dir.path());
+        write(dir.path(), "/home/user/key.pem", "private");
+        write(
+            dir.path(),
+            "/home/.snapshots/12/snapshot/user/key.pem",
+            "private",
+        );
+        write(
+            dir.path(),
+            "/run/timeshift/backup/timeshift-btrfs/snapshots/2024-05-01_10-00-01/@home/user/key.pem",
+            "private",
+        );
+
+        let btrfs = filesystem(
+            FilesystemType::Btrfs {
+                subvolume: true,
+                compression: None,
+                nodatacow: false,
+            },
+            "40 1 0:40 /@home /home rw - btrfs /dev/sda2 rw,subvol=/@home\n",
+        );
+        let search = find_snapshot_copies(&root, Path::new("/home/user/key.pem"), &btrfs);
+        let copies = search.copies;
+
+        // Not a real btrfs filesystem, so the subvolume listing fails and
+        // says so rather than reporting no snapshots
+        assert_eq!(search.unchecked.len(), 1);
+        assert!(search.unchecked[0].starts_with("btrfs snapshots: "));
+        assert_eq!(copies.len(), 2);
+        assert_eq!(copies[0].kind, SnapshotKind::Snapper);
+        assert_eq!(copies[0].removal_hint(), "snapper delete 12");
+        assert_eq!(copies[1].kind, SnapshotKind::Timeshift);
+        assert_eq!(copies[1].name, "2024-05-01_10-00-01");
+    }
+
+    fn subvolume(id: u64, path: &str, uuid: u8, parent_uuid: u8) -> BtrfsSubvolume {
+        BtrfsSubvolume {
+            id,
+            path: path.to_string(),
+            uuid: [uuid; 16],
+            parent_uuid: [parent_uuid; 16],
+        }
+    }
+
+    #[test]
+    fn test_btrfs_snapshots_of_the_file_subvolume_only() {
+        let dir = tempdir().unwrap();
+        let root = SysRoot::new(dir.path());
+        write(
+            dir.path(),
+            "/proc/self/mountinfo",
+            "40 1 0:40 /@ / rw - btrfs /dev/sda2 rw,subvol=/@\n\
+             41 40 0:40 /@home /home rw - btrfs /dev/sda2 rw,subvol=/@home\n\
+             42 40 0:40 / /mnt/top rw - btrfs /dev/sda2 rw,subvol=/\n",
+        );
+        write(dir.path(), "/home/user/key.pem", "private");
+        write(
+            dir.path(),
+            "/mnt/top/@snapshots/home-1/user/key.pem",
+            "private",
+        );
+        write(
+            dir.path(),
+            "/mnt/top/@snapshots/home-1-copy/user/key.pem",
+            "older",
+        );
+        // Same relative path, but in a snapshot of the root subvolume
+        write(
+            dir.path(),
+            "/mnt/top/@snapshots/root-1/user/key.pem",
+            "unrelated",
+        );
+
+        let listing = BtrfsListing {
+            subvolumes: vec![
+                subvolume(256, "@", 1, 0),
+                subvolume(257, "@home", 2, 0),
+                subvolume(258, "@snapshots", 3, 0),
+                subvolume(259, "@snapshots/root-1", 4, 1),
+                subvolume(260, "@snapshots/home-1", 5, 2),
+                subvolume(261, "@snapshots/home-1-copy", 6, 5),
+            ],
+            source: 257,
+        };
+        let btrfs = filesystem(
+            FilesystemType::Btrfs {
+                subvolume: true,
+                compression: None,
+                nodatacow: false,
+            },
+            "41 40 0:40 /@home /home rw - btrfs /dev/sda2 rw,subvol=/@home\n",
+        );
+        let search = search_snapshots(&root, Path::new("/home/user/key.pem"), &btrfs, || {
+            Ok(listing)
+        });
+
+        assert!(search.unchecked.is_empty());
+        let paths: Vec<_> = search.copies.iter().map(|c| c.path.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("/mnt/top/@snapshots/home-1/user/key.pem"),
+                PathBuf::from("/mnt/top/@snapshots/home-1-copy/user/key.pem"),
+            ]
+        );
+        assert!(search.copies.iter().all(|c| c.kind == SnapshotKind::Btrfs));
+        assert!(search.copies[0].same_content);
+        assert!(!search.copies[1].same_content);
+    }
+
+    #[test]
+    fn test_btrfs_subvolume_without_uuid_is_unchecked() {
+        let listing = BtrfsListing {
+            subvolumes: vec![subvolume(257, "@home", 0, 0), subvolume(260, "snap", 5, 0)],
+            source: 257,
+        };
+        assert_eq!(listing.snapshots_of_source(), None);
+    }
+
+    #[test]
+    fn test_locate_btrfs_subvolume() {
+        let subvolumes = ["@snapshots/home-1", "@/backup"];
+
+        let mounts = parse_mountinfo(
+            "40 1 0:40 /@ / rw - btrfs /dev/sda2 rw,subvol=/@\n\
+             41 40 0:41 /@home /home rw - btrfs /dev/sda2 rw,subvol=/@home\n\
+             42 40 0:42 / /mnt/top rw - btrfs /dev/sda2 rw,subvol=/\n",
+        );
+        assert_eq!(
+            locate_subvolume(&mounts, "/dev/sda2", subvolumes[0]),
+            Some(PathBuf::from("/mnt/top/@snapshots/home-1"))
+        );
+        assert_eq!(
+            locate_subvolume(&mounts, "/dev/sda2", subvolumes[1]),
+            Some(PathBuf::from("/backup"))
+        );
+        assert_eq!(locate_subvolume(&mounts, "/dev/sdb1", subvolumes[0]), None);
+    }
+}
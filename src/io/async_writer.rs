@@ -1,8 +1,7 @@
 use crate::{patterns::WipePattern, Result};
 use std::collections::VecDeque;
 use std::fs::File;
-use std::io::{Seek, SeekFrom, Write};
-use std::path::Path;
+use std::os::unix::fs::FileExt;
 use std::sync::{Arc, Mutex};
 use tokio::sync::Semaphore;
 use tokio::task;
@@ -56,13 +55,13 @@ impl AsyncWiper {
 
     pub async fn wipe_chunk(
         &self,
-        path: &Path,
+        file: &File,
         mut pattern: WipePattern,
         start_offset: u64,
         chunk_size: usize,
     ) -> Result<()> {
         let _permit = self.concurrency_limit.acquire().await.unwrap();
-        let path_owned = path.to_path_buf();
+        let file = file.try_clone()?;
         let buffer_pool = self.buffer_pool.clone();
 
         task::spawn_blocking(move || -> Result<()> {
@@ -74,9 +73,7 @@ impl AsyncWiper {
             pattern.generate(chunk);
 
             // Write to file
-            let mut file = File::options().write(true).open(&path_owned)?;
-            file.seek(SeekFrom::Start(start_offset))?;
-            file.write_all(chunk)?;
+            file.write_all_at(chunk, start_offset)?;
             file.sync_data()?; // Use sync_data for better performance than sync_all
 
             buffer_pool.return_buffer(buffer);
@@ -89,12 +86,12 @@ impl AsyncWiper {
 
     pub async fn parallel_wipe(
         &self,
-        path: &Path,
+        file: &File,
         pattern: WipePattern,
         file_size: u64,
         chunk_size: usize,
     ) -> Result<()> {
-        let chunks = (file_size as usize + chunk_size - 1) / chunk_size;
+        let chunks = (file_size as usize).div_ceil(chunk_size);
         let mut tasks = Vec::new();
 
         for i in 0..chunks {
@@ -105,7 +102,7 @@ impl AsyncWiper {
                 chunk_size
             };
 
-            let task = self.wipe_chunk(path, pattern.clone(), start_offset, current_chunk_size);
+            let task = self.wipe_chunk(file, pattern.clone(), start_offset, current_chunk_size);
             tasks.push(task);
         }
 
@@ -123,7 +120,7 @@ impl Clone for WipePattern {
             WipePattern::Random(_) => {
                 // Create a new random generator for each clone
                 use rand::SeedableRng;
-                WipePattern::Random(rand_chacha::ChaCha20Rng::from_entropy())
+                WipePattern::Random(Box::new(rand_chacha::ChaCha20Rng::from_entropy()))
             }
             WipePattern::Fixed(byte) => WipePattern::Fixed(*byte),
             WipePattern::Zeros => WipePattern::Zeros,
@@ -166,7 +163,7 @@ mod tests {
         let pattern = WipePattern::Fixed(0xAA);
 
         wiper
-            .parallel_wipe(temp_file.path(), pattern, 1024, 256)
+            .parallel_wipe(temp_file.as_file(), pattern, 1024, 256)
             .await?;
 
         // Verify the file was wiped
@@ -1,16 +1,102 @@
 pub mod async_writer;
-
-use crate::{patterns::WipePattern, storage::StorageType, AmaterasuConfig, Result};
+pub mod pinned;
+pub mod sync;
+
+use crate::filesystem::compression::{self, CompressionSource};
+use crate::filesystem::cow::CowStatus;
+use crate::filesystem::encryption::{self, Encryption, KeyStatus};
+use crate::filesystem::f2fs::TrimStatus;
+use crate::filesystem::fiemap::{self, Extent, ExtentCheck};
+use crate::filesystem::flags;
+use crate::filesystem::overlay::{self, OverlayCopy};
+use crate::filesystem::shared::{self, SharedExtents};
+use crate::filesystem::snapshots::{self, SnapshotCopy};
+use crate::{
+    patterns::WipePattern, storage::StorageType, AmaterasuConfig, HardlinkPolicy, NotWipeable,
+    Result, SharedExtentPolicy,
+};
+use anyhow::Context;
 use async_writer::AsyncWiper;
 use indicatif::{ProgressBar, ProgressStyle};
-use std::fs::{File, OpenOptions};
-use std::io::{Seek, SeekFrom, Write};
-use std::path::Path;
+use pinned::PinnedEntry;
+use std::fs::File;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
 use tokio::task;
 
+/// What happened to one file, returned once it has been wiped and removed
+#[derive(Debug, Clone, Default)]
+pub struct WipeOutcome {
+    pub path: PathBuf,
+    pub passes: usize,
+    /// Why fixed-pattern passes were replaced with random data; empty when
+    /// the file's blocks are stored as written
+    pub compression: Vec<CompressionSource>,
+    /// Whether the passes could overwrite the original blocks
+    pub cow: CowStatus,
+    /// Whether the file's physical extents survived the passes unchanged
+    pub extents: ExtentCheck,
+    /// Extents shared with other files when the wipe started
+    pub shared: Option<SharedExtents>,
+    /// Copies of the file kept in snapshots, which the wipe doesn't touch
+    pub snapshots: Vec<SnapshotCopy>,
+    /// Whether the filesystem erased the file's blocks after the passes
+    pub trim: TrimStatus,
+    /// Layers holding the file when it lives on overlayfs
+    pub overlay: Option<OverlayCopy>,
+    /// Encryption at rest; with it, removing the key matters more than
+    /// the passes
+    pub encryption: Encryption,
+    /// Immutable or append-only attributes cleared so the file could be
+    /// wiped
+    pub cleared_attributes: Vec<ClearedAttribute>,
+}
+
+/// An inode attribute (`chattr`) removed before wiping
+#[derive(Debug, Clone)]
+pub struct ClearedAttribute {
+    pub path: PathBuf,
+    /// "immutable" or "append-only"
+    pub attribute: &'static str,
+}
+
+/// Attributes that make the kernel refuse writes, renames and unlinks, even
+/// to root, with their `chattr` letters
+const PROTECTION_FLAGS: [(u32, &str, char); 2] = [
+    (flags::FS_IMMUTABLE_FL, "immutable", 'i'),
+    (flags::FS_APPEND_FL, "append-only", 'a'),
+];
+
+/// Everything a run did: one outcome per wiped file and per filesystem
+#[derive(Debug, Clone, Default)]
+pub struct WipeReport {
+    pub files: Vec<WipeOutcome>,
+    /// Files skipped under `--force` because they can't be wiped where they live
+    pub refused: Vec<PathBuf>,
+    /// Directories renamed and removed after their contents
+    pub directories: Vec<PathBuf>,
+    /// Symlinks, special files and further names of wiped files, removed
+    /// without being overwritten
+    pub unlinked: Vec<PathBuf>,
+    /// Files, links and directories that failed under `--force`
+    pub failed: Vec<PathBuf>,
+    /// Syncing failed under `--force`, so writes may not be on disk
+    pub sync_failed: bool,
+    pub filesystems: Vec<sync::FilesystemOutcome>,
+}
+
+impl WipeReport {
+    /// Whether anything genuinely failed, as opposed to being refused
+    pub fn has_failures(&self) -> bool {
+        !self.failed.is_empty() || self.sync_failed
+    }
+}
+
 pub struct FileWiper {
     storage_type: StorageType,
     config: AmaterasuConfig,
+    passes: Option<usize>,
+    known_links: usize,
 }
 
 impl FileWiper {
@@ -18,29 +104,120 @@ impl FileWiper {
         Self {
             storage_type: storage_type.clone(),
             config,
+            passes: None,
+            known_links: 0,
         }
     }
 
-    pub async fn wipe(&self, path: &Path, _pattern: WipePattern) -> Result<()> {
-        let file_size = std::fs::metadata(path)?.len();
+    /// Force a specific number of passes instead of the storage-aware default
+    pub fn with_passes(mut self, passes: Option<usize>) -> Self {
+        self.passes = passes;
+        self
+    }
+
+    /// How many other names of the file the caller removes after the wipe
+    pub fn with_known_links(mut self, known_links: usize) -> Self {
+        self.known_links = known_links;
+        self
+    }
+
+    pub async fn wipe(&self, path: &Path, _pattern: WipePattern) -> Result<WipeOutcome> {
+        // Every step below works on these descriptors; the path is only
+        // looked up again to confirm it still names the same inode
+        let mut pinned = PinnedEntry::open_file(path)?;
+        let metadata = pinned.file().metadata()?;
+        let file_size = metadata.len();
+        let canonical = pinned
+            .canonical_path()
+            .unwrap_or_else(|| path.to_path_buf());
 
         // Detect filesystem type and apply optimizations
-        let filesystem_type = crate::filesystem::detector::detect_filesystem_type(path)
-            .unwrap_or(crate::filesystem::FilesystemType::Unknown);
-        let fs_optimizer = filesystem_type.get_optimizer();
+        let filesystem =
+            crate::filesystem::detector::detect_filesystem_of(pinned.file(), &canonical)
+                .unwrap_or_else(|_| crate::filesystem::FilesystemInfo::unknown());
+        let fs_optimizer = filesystem.get_optimizer();
 
         println!("🔥 Wiping: {}", path.display());
         println!("Size: {} bytes", file_size);
         println!("Storage: {:?}", self.storage_type);
-        println!("Filesystem: {:?}", filesystem_type);
+        match &filesystem.mount {
+            Some(mount) => println!(
+                "Filesystem: {:?} ({} mounted on {})",
+                filesystem.fs_type,
+                mount.source,
+                mount.mount_point.display()
+            ),
+            None => println!("Filesystem: {:?}", filesystem.fs_type),
+        }
+
+        self.check_remote(path, &filesystem)?;
+        self.check_hard_links(path, metadata.nlink())?;
 
         // Apply filesystem-specific pre-wipe setup
         fs_optimizer.pre_wipe_setup(path)?;
+        let overlay = self.check_overlay(path, &canonical, &filesystem)?;
+
+        // Memory-backed files have no disk blocks to map
+        let extents_before =
+            (!filesystem.fs_type.is_memory_backed()).then(|| fiemap::map_extents(pinned.file()));
+        let shared = match &extents_before {
+            Some(Ok(extents)) => {
+                self.check_shared_extents(path, pinned.file(), extents, &filesystem)?
+            }
+            _ => None,
+        };
 
-        let patterns = crate::patterns::create_storage_aware_pattern_sequence(
-            &self.config.mode,
-            &self.storage_type,
-        );
+        // Only once nothing refuses the file may its protection come off;
+        // the directory's is put back when this returns, on error too
+        let (mut file_flags, dir_flags) = self.check_attributes(path, &mut pinned)?;
+        let cleared_attributes = file_flags
+            .attributes
+            .iter()
+            .chain(&dir_flags.attributes)
+            .cloned()
+            .collect();
+        let file = pinned.file();
+        let encryption = self.check_encryption(pinned.dir(), &filesystem);
+        let cow = fs_optimizer.disable_cow(file);
+
+        let patterns = match self.passes {
+            Some(passes) => {
+                crate::patterns::create_pattern_sequence_with_passes(&self.config.mode, passes)
+            }
+            None => crate::patterns::create_storage_aware_pattern_sequence(
+                &self.config.mode,
+                &self.storage_type,
+            ),
+        };
+
+        let mut outcome = WipeOutcome {
+            path: path.to_path_buf(),
+            passes: patterns.len(),
+            compression: compression::detect_compression(file, &filesystem),
+            cow,
+            snapshots: self.find_snapshot_copies(&canonical, &filesystem),
+            overlay,
+            encryption,
+            cleared_attributes,
+            shared,
+            ..WipeOutcome::default()
+        };
+
+        let patterns = if outcome.compression.is_empty() {
+            patterns
+        } else {
+            let sources: Vec<_> = outcome
+                .compression
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            println!(
+                "⚠️  Compressed or deduplicated data ({}): all passes use random data",
+                sources.join(", ")
+            );
+            crate::patterns::make_incompressible(patterns)
+        };
+        let total_passes = patterns.len();
         println!(
             "Passes: {} (optimized for storage and filesystem)",
             patterns.len()
@@ -67,11 +244,7 @@ impl FileWiper {
                 pb.set_message(format!(
                     "Pass {}/{} ({})",
                     pass_num + 1,
-                    crate::patterns::create_storage_aware_pattern_sequence(
-                        &self.config.mode,
-                        &self.storage_type
-                    )
-                    .len(),
+                    total_passes,
                     pattern.name()
                 ));
             }
@@ -79,10 +252,10 @@ impl FileWiper {
             // Use async implementation for large files, fallback for small ones
             if file_size > 1024 * 1024 {
                 // 1MB threshold
-                self.async_wipe_pass(path, pattern, file_size, progress_bar.clone(), &async_wiper)
+                self.async_wipe_pass(file, pattern, file_size, progress_bar.clone(), &async_wiper)
                     .await?;
             } else {
-                self.wipe_pass(path, pattern, file_size, progress_bar.clone())
+                self.wipe_pass(file, pattern, file_size, progress_bar.clone())
                     .await?;
             }
         }
@@ -91,41 +264,325 @@ impl FileWiper {
             pb.finish_with_message("Wipe completed");
         }
 
+        file.sync_all()
+            .with_context(|| format!("Failed to fsync {}", path.display()))?;
+
+        outcome.extents = match extents_before.map(|before| (before, fiemap::map_extents(file))) {
+            Some((Ok(before), Ok(after))) => ExtentCheck::compare(&before, &after),
+            Some((Err(e), _)) | Some((_, Err(e))) => ExtentCheck::Unavailable(format!("{:#}", e)),
+            None => ExtentCheck::NotChecked,
+        };
+        match &outcome.extents {
+            ExtentCheck::InPlace => {
+                println!("✅ Overwritten in place (physical extents unchanged)")
+            }
+            ExtentCheck::Relocated { bytes } => {
+                println!(
+                    "⚠️  Extents moved during the wipe: {} bytes of original data likely remain on disk",
+                    bytes
+                );
+            }
+            ExtentCheck::Unavailable(reason) => {
+                println!("⚠️  Could not check physical extents: {}", reason)
+            }
+            ExtentCheck::NotChecked => {}
+        }
+
         if self.config.verify {
-            self.verify_wipe(path, file_size).await?;
+            self.verify_wipe(file, file_size).await?;
         }
 
+        outcome.trim = fs_optimizer.trim_file(file);
+
         // Apply filesystem-specific post-wipe cleanup
-        fs_optimizer.post_wipe_cleanup(path)?;
+        fs_optimizer.post_wipe_cleanup(file)?;
+
+        // Don't rename or unlink whatever the name points to now if it is
+        // no longer the file that was overwritten
+        pinned.verify()?;
 
         // Wipe metadata before final deletion if enabled
         if self.config.wipe_metadata {
             let metadata_wiper =
-                crate::security::metadata::MetadataWiper::new(self.config.metadata_passes);
-            metadata_wiper.wipe_file_metadata(path).await?;
+                crate::security::metadata::MetadataWiper::new(self.config.metadata_passes)
+                    .with_name_rules(fs_optimizer.name_rules())
+                    .with_fixed_timestamp(self.config.fixed_timestamp)
+                    .with_xattr_overwrite(
+                        !filesystem.fs_type.supports_cow()
+                            && !filesystem.fs_type.is_memory_backed(),
+                    );
+            metadata_wiper.wipe_pinned(pinned).await?;
         } else {
             // Simple file removal without metadata wiping
-            std::fs::remove_file(path)?;
+            crate::security::metadata::truncate_and_sync(file)?;
+            pinned.remove()?;
+            pinned.sync_dir()?;
         }
+        file_flags.keep_cleared();
 
+        Ok(outcome)
+    }
+
+    /// Refuse files whose storage is out of reach unless the user opted in
+    fn check_remote(
+        &self,
+        path: &Path,
+        filesystem: &crate::filesystem::FilesystemInfo,
+    ) -> Result<()> {
+        let source = filesystem
+            .mount
+            .as_ref()
+            .map(|mount| mount.source.as_str())
+            .unwrap_or("unknown");
+        let reason = match &filesystem.fs_type {
+            crate::filesystem::FilesystemType::Network { protocol } => format!(
+                "it is on a {} share ({}); overwriting it here cannot reach the server's disks, caches or snapshots",
+                protocol, source
+            ),
+            crate::filesystem::FilesystemType::Fuse { subtype } => format!(
+                "it is on a FUSE filesystem ({}, {}); the daemon decides where and how its data is stored",
+                subtype, source
+            ),
+            _ => return Ok(()),
+        };
+
+        if !self.config.allow_remote {
+            return Err(NotWipeable::new(
+                path,
+                format!("{} (use --allow-remote to try anyway)", reason),
+            )
+            .into());
+        }
+
+        println!("⚠️  Wiping anyway (--allow-remote): {}", reason);
         Ok(())
     }
 
-    async fn wipe_pass(
+    /// Make sure neither the file nor its directory is immutable or
+    /// append-only, then open the file for writing. Returns what was
+    /// cleared on the file and on the directory, in that order.
+    fn check_attributes(
         &self,
         path: &Path,
+        pinned: &mut PinnedEntry,
+    ) -> Result<(ClearedFlags, ClearedFlags)> {
+        let clear = self.config.clear_immutable;
+        let file_flags = check_protection(path, path, pinned.file(), clear)?;
+        let dir_flags = check_protection(path, pinned.dir_path(), pinned.dir(), clear)?;
+
+        if !pinned.is_writable() {
+            pinned.reopen_writable()?;
+        }
+        Ok((file_flags, dir_flags))
+    }
+
+    /// Refuse files with other names, whose content would be destroyed
+    /// too, unless the user asked to wipe all of them
+    fn check_hard_links(&self, path: &Path, nlink: u64) -> Result<()> {
+        if nlink <= 1 {
+            return Ok(());
+        }
+        let others = nlink - 1;
+        if self.config.hardlinks == HardlinkPolicy::Refuse {
+            return Err(NotWipeable::new(
+                path,
+                format!(
+                    "it has {} other hard link(s) whose content would be destroyed too (use --hardlinks wipe-all to wipe it and remove its names among the targets)",
+                    others
+                ),
+            )
+            .into());
+        }
+
+        println!(
+            "🔗 {} other hard link(s), {} among the targets and removed after the wipe",
+            others, self.known_links
+        );
+        let outside = others.saturating_sub(self.known_links as u64);
+        if outside > 0 {
+            println!(
+                "⚠️  {} name(s) outside the targets will remain, pointing to the emptied file",
+                outside
+            );
+        }
+        Ok(())
+    }
+
+    /// Refuse files that only exist in read-only overlay layers and point
+    /// out lower-layer originals that survive the wipe of the upper copy
+    fn check_overlay(
+        &self,
+        path: &Path,
+        canonical: &Path,
+        filesystem: &crate::filesystem::FilesystemInfo,
+    ) -> Result<Option<OverlayCopy>> {
+        let copy = overlay::locate_layers(&crate::sysroot::SysRoot::host(), canonical, filesystem);
+
+        match &copy {
+            Some(OverlayCopy::LowerOnly { lower }) => {
+                let layers: Vec<_> = lower.iter().map(|l| l.display().to_string()).collect();
+                return Err(NotWipeable::new(
+                    path,
+                    format!(
+                        "it only exists in read-only overlay layer(s) {}; writing would copy it up and deleting would only hide it",
+                        layers.join(", ")
+                    ),
+                )
+                .into());
+            }
+            Some(OverlayCopy::CopiedUp { upper, lower }) => {
+                println!(
+                    "📁 Overlayfs: wiping the upper-layer copy {}",
+                    upper.display()
+                );
+                for original in lower {
+                    println!(
+                        "⚠️  Original remains in lower layer: {}",
+                        original.display()
+                    );
+                }
+            }
+            Some(OverlayCopy::Unknown(reason)) => {
+                println!("⚠️  Overlayfs: cannot inspect layers ({})", reason);
+                println!("   Note: A copy in a read-only lower layer would survive the wipe");
+            }
+            None => {}
+        }
+
+        Ok(copy)
+    }
+
+    /// Show whether the file is encrypted at rest and which key protects it
+    fn check_encryption(
+        &self,
+        dir: &File,
+        filesystem: &crate::filesystem::FilesystemInfo,
+    ) -> Encryption {
+        let encryption = encryption::detect_encryption(dir, filesystem);
+        if encryption == Encryption::None {
+            return encryption;
+        }
+
+        println!("🔐 Encryption: {}", encryption);
+        match &encryption {
+            Encryption::Fscrypt(policy) if policy.key == KeyStatus::Absent => {}
+            _ => println!(
+                "   Note: Removing the key makes the data unrecoverable; overwriting only narrows what it protects"
+            ),
+        }
+
+        if self.config.encryption_report {
+            let mount_point = filesystem
+                .mount
+                .as_ref()
+                .map(|mount| mount.mount_point.display().to_string())
+                .unwrap_or_else(|| "<mountpoint>".to_string());
+            for identifier in encryption.key_identifiers() {
+                println!("   Key identifier: {}", identifier);
+                match &encryption {
+                    Encryption::Fscrypt(policy) if policy.version == 2 => {
+                        println!("     fscryptctl remove_key {} {}", identifier, mount_point)
+                    }
+                    Encryption::Fscrypt(_) => println!(
+                        "     keyctl unlink $(keyctl search @s logon fscrypt:{})",
+                        identifier
+                    ),
+                    _ => println!("     keyctl unlink $(keyctl search @u user {})", identifier),
+                }
+            }
+        }
+
+        encryption
+    }
+
+    fn find_snapshot_copies(
+        &self,
+        canonical: &Path,
+        filesystem: &crate::filesystem::FilesystemInfo,
+    ) -> Vec<SnapshotCopy> {
+        let search = snapshots::find_snapshot_copies(
+            &crate::sysroot::SysRoot::host(),
+            canonical,
+            filesystem,
+        );
+        let copies = search.copies;
+
+        for reason in &search.unchecked {
+            println!("⚠️  Snapshots not checked for copies: {}", reason);
+        }
+
+        for copy in &copies {
+            let state = match (copy.same_inode, copy.same_content) {
+                (_, true) => "identical content",
+                (true, false) => "older version of this file",
+                (false, false) => "different content",
+            };
+            println!(
+                "📸 Copy in {} snapshot {}: {} ({})",
+                copy.kind,
+                copy.name,
+                copy.path.display(),
+                state
+            );
+        }
+
+        if self.config.snapshot_report && !copies.is_empty() {
+            println!("   Delete these snapshots for the data to be gone:");
+            let mut hints: Vec<String> = copies.iter().map(SnapshotCopy::removal_hint).collect();
+            hints.dedup();
+            for hint in hints {
+                println!("     {}", hint);
+            }
+        }
+
+        copies
+    }
+
+    fn check_shared_extents(
+        &self,
+        path: &Path,
+        file: &File,
+        extents: &[Extent],
+        filesystem: &crate::filesystem::FilesystemInfo,
+    ) -> Result<Option<SharedExtents>> {
+        let Some(shared) = shared::find_shared(file, extents, &filesystem.fs_type) else {
+            return Ok(None);
+        };
+
+        let summary = format!(
+            "{} bytes in {} extents are shared with other files or snapshots",
+            shared.bytes, shared.extents
+        );
+        if self.config.shared_extents == SharedExtentPolicy::Refuse {
+            return Err(NotWipeable::new(
+                path,
+                format!("{}; overwriting would only unshare them", summary),
+            )
+            .into());
+        }
+
+        println!("⚠️  {}", summary);
+        println!("   Note: Overwriting gives this file private copies; the shared data remains");
+        for sharer in &shared.sharers {
+            println!(
+                "   Shared with inode {} in subvolume {}",
+                sharer.inode, sharer.root
+            );
+        }
+        Ok(Some(shared))
+    }
+
+    async fn wipe_pass(
+        &self,
+        file: &File,
         mut pattern: WipePattern,
         file_size: u64,
         progress_bar: Option<ProgressBar>,
     ) -> Result<()> {
         let block_size = self.storage_type.get_optimal_block_size();
-        let path_owned = path.to_path_buf();
+        let file = file.try_clone()?;
 
         task::spawn_blocking(move || -> Result<()> {
-            let mut file = OpenOptions::new().write(true).open(&path_owned)?;
-
-            file.seek(SeekFrom::Start(0))?;
-
             let mut buffer = vec![0u8; block_size];
             let mut bytes_written = 0u64;
 
@@ -134,8 +591,7 @@ impl FileWiper {
                 let chunk = &mut buffer[..chunk_size];
 
                 pattern.generate(chunk);
-                file.write_all(chunk)?;
-                file.flush()?;
+                file.write_all_at(chunk, bytes_written)?;
 
                 bytes_written += chunk_size as u64;
 
@@ -154,7 +610,7 @@ impl FileWiper {
 
     async fn async_wipe_pass(
         &self,
-        path: &Path,
+        file: &File,
         pattern: WipePattern,
         file_size: u64,
         progress_bar: Option<ProgressBar>,
@@ -163,14 +619,10 @@ impl FileWiper {
         let chunk_size = self.storage_type.get_optimal_block_size() * 16; // Use larger chunks for parallel processing
 
         // Set up progress tracking closure
-        let progress_callback = if let Some(pb) = progress_bar {
-            Some(move |bytes: usize| pb.inc(bytes as u64))
-        } else {
-            None
-        };
+        let progress_callback = progress_bar.map(|pb| move |bytes: usize| pb.inc(bytes as u64));
 
         async_wiper
-            .parallel_wipe(path, pattern, file_size, chunk_size)
+            .parallel_wipe(file, pattern, file_size, chunk_size)
             .await?;
 
         // If we have a progress callback, update it with the total file size
@@ -181,13 +633,11 @@ impl FileWiper {
         Ok(())
     }
 
-    async fn verify_wipe(&self, path: &Path, file_size: u64) -> Result<()> {
+    async fn verify_wipe(&self, file: &File, file_size: u64) -> Result<()> {
         println!("🔍 Verifying wipe...");
 
-        let path_owned = path.to_path_buf();
+        let file = file.try_clone()?;
         task::spawn_blocking(move || -> Result<()> {
-            use std::io::Read;
-            let mut file = File::open(&path_owned)?;
             let mut buffer = vec![0u8; 8192];
             let mut bytes_read = 0u64;
             let mut pattern_found = false;
@@ -195,7 +645,7 @@ impl FileWiper {
             while bytes_read < file_size {
                 let bytes_to_read = std::cmp::min(buffer.len(), (file_size - bytes_read) as usize);
                 let chunk = &mut buffer[..bytes_to_read];
-                let n = file.read(chunk)?;
+                let n = file.read_at(chunk, bytes_read)?;
                 if n == 0 {
                     break;
                 }
@@ -222,3 +672,150 @@ impl FileWiper {
         Ok(())
     }
 }
+
+/// Attributes removed by `check_protection`, set again when dropped
+/// unless the entry is gone
+pub(crate) struct ClearedFlags {
+    /// A handle on the entry and its original flags
+    restore: Option<(File, u32)>,
+    pub(crate) attributes: Vec<ClearedAttribute>,
+}
+
+impl ClearedFlags {
+    fn none() -> Self {
+        Self {
+            restore: None,
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Don't put the attributes back: the entry was removed
+    pub(crate) fn keep_cleared(&mut self) {
+        self.restore = None;
+    }
+}
+
+impl Drop for ClearedFlags {
+    fn drop(&mut self) {
+        let Some((handle, original)) = self.restore.take() else {
+            return;
+        };
+        let Some(subject) = self.attributes.first().map(|a| a.path.display()) else {
+            return;
+        };
+        match flags::set_flags(&handle, original) {
+            Ok(()) => println!("🔒 Restored attributes on {}", subject),
+            Err(e) => eprintln!(
+                "Warning: Failed to restore the attributes of {}: {:#}",
+                subject, e
+            ),
+        }
+    }
+}
+
+/// The protection attributes among inode flags `current`: their mask,
+/// names and `chattr` letters
+fn protection_attributes(current: u32) -> Option<(u32, Vec<&'static str>, String)> {
+    let set: Vec<_> = PROTECTION_FLAGS
+        .iter()
+        .filter(|(flag, _, _)| current & flag != 0)
+        .collect();
+    if set.is_empty() {
+        return None;
+    }
+    Some((
+        set.iter().fold(0, |mask, (flag, _, _)| mask | flag),
+        set.iter().map(|(_, name, _)| *name).collect(),
+        set.iter().map(|(_, _, letter)| *letter).collect(),
+    ))
+}
+
+/// Why `target` can't be wiped while `subject` (the file itself or its
+/// directory) has inode flags `current`, if they protect it
+pub(crate) fn protection_refusal(
+    target: &Path,
+    subject: &Path,
+    current: u32,
+) -> Option<NotWipeable> {
+    let (_, attributes, letters) = protection_attributes(current)?;
+    let names = attributes.join(" and ");
+    let reason = if subject == target {
+        format!(
+            "it is {} (chattr +{}), which stops even root from overwriting, renaming or removing it",
+            names, letters
+        )
+    } else {
+        format!(
+            "its directory {} is {} (chattr +{}), which stops even root from renaming or removing entries in it",
+            subject.display(),
+            names,
+            letters
+        )
+    };
+    Some(NotWipeable::new(
+        target,
+        format!(
+            "{} (use --clear-immutable to clear the attribute first; needs CAP_LINUX_IMMUTABLE)",
+            reason
+        ),
+    ))
+}
+
+/// Refuse `target` when `subject` (the file itself or its directory) is
+/// immutable or append-only, or with `clear` remove those attributes, which
+/// takes CAP_LINUX_IMMUTABLE. Filesystems without inode flags pass.
+pub(crate) fn check_protection(
+    target: &Path,
+    subject: &Path,
+    handle: &File,
+    clear: bool,
+) -> Result<ClearedFlags> {
+    let Ok(current) = flags::get_flags(handle) else {
+        return Ok(ClearedFlags::none());
+    };
+    if !clear {
+        return match protection_refusal(target, subject, current) {
+            Some(refusal) => Err(refusal.into()),
+            None => Ok(ClearedFlags::none()),
+        };
+    }
+    let Some((mask, attributes, letters)) = protection_attributes(current) else {
+        return Ok(ClearedFlags::none());
+    };
+    let names = attributes.join(" and ");
+
+    let restore = handle
+        .try_clone()
+        .with_context(|| format!("Failed to duplicate the handle on {}", subject.display()))?;
+    if let Err(e) = flags::set_flags(handle, current & !mask) {
+        if e.downcast_ref::<nix::errno::Errno>() == Some(&nix::errno::Errno::EPERM) {
+            anyhow::bail!(
+                "Failed to clear the {} attribute of {}: needs CAP_LINUX_IMMUTABLE",
+                names,
+                subject.display()
+            );
+        }
+        return Err(e.context(format!(
+            "Failed to clear the {} attribute of {}",
+            names,
+            subject.display()
+        )));
+    }
+
+    println!(
+        "🔓 Cleared {} attribute on {} (chattr -{})",
+        names,
+        subject.display(),
+        letters
+    );
+    Ok(ClearedFlags {
+        restore: Some((restore, current)),
+        attributes: attributes
+            .into_iter()
+            .map(|attribute| ClearedAttribute {
+                path: subject.to_path_buf(),
+                attribute,
+            })
+            .collect(),
+    })
+}
@@ -0,0 +1,487 @@
+use crate::Result;
+use anyhow::{bail, Context};
+use nix::errno::Errno;
+use nix::fcntl::{open, openat, renameat, renameat2, OFlag, RenameFlags};
+use nix::sys::stat::{fstatat, Mode, SFlag};
+use nix::unistd::{unlinkat, UnlinkatFlags};
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Component, Path, PathBuf};
+
+/// A file or directory being wiped, held by descriptors so no later step
+/// resolves its path again: a symlink or another file swapped in mid-run
+/// can't redirect writes, renames or the unlink. Every rename and the
+/// final removal first checks that the name still refers to the inode that
+/// was opened.
+#[derive(Debug)]
+pub struct PinnedEntry {
+    /// Directory holding the entry, opened once
+    dir: File,
+    dir_path: PathBuf,
+    /// Current name within `dir`; changes with every rename
+    name: OsString,
+    handle: File,
+    dev: u64,
+    ino: u64,
+    is_dir: bool,
+    /// False when a file could only be opened read-only, e.g. because it
+    /// is immutable or append-only
+    writable: bool,
+}
+
+impl PinnedEntry {
+    /// Open a regular file for reading and writing, or only for reading
+    /// when writing is not permitted (see `is_writable`). Symlinks,
+    /// devices, FIFOs and sockets are refused without being opened.
+    pub fn open_file(path: &Path) -> Result<Self> {
+        Self::open(path, false)
+    }
+
+    /// Open a directory, refusing a symlink to one
+    pub fn open_dir(path: &Path) -> Result<Self> {
+        Self::open(path, true)
+    }
+
+    fn open(path: &Path, is_dir: bool) -> Result<Self> {
+        let Some(name) = path.file_name() else {
+            bail!("{} does not name a file", path.display());
+        };
+        let dir_path = super::sync::parent_dir(path).to_path_buf();
+        let dir = open_directory(&dir_path)?;
+
+        // An O_PATH descriptor pins the inode without opening it, so the
+        // type can be checked before anything with side effects happens
+        let path_fd = openat(
+            dir.as_raw_fd(),
+            name,
+            OFlag::O_PATH | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+        let pinned = unsafe { File::from_raw_fd(path_fd) };
+        let metadata = pinned.metadata()?;
+        let file_type = metadata.file_type();
+        if file_type.is_symlink() {
+            bail!("{} is a symbolic link; not following it", path.display());
+        }
+        if is_dir && !file_type.is_dir() {
+            bail!("{} is not a directory", path.display());
+        }
+        if !is_dir && !file_type.is_file() {
+            bail!("{} is not a regular file", path.display());
+        }
+
+        let identity = (metadata.dev(), metadata.ino());
+        let (handle, writable) = if is_dir {
+            let flags = OFlag::O_RDONLY | OFlag::O_DIRECTORY;
+            (
+                open_pinned(&dir, name, &pinned, flags, identity, path)?,
+                false,
+            )
+        } else {
+            match open_pinned(&dir, name, &pinned, OFlag::O_RDWR, identity, path) {
+                Ok(handle) => (handle, true),
+                // Immutable and append-only files refuse O_RDWR even to
+                // root; a read-only handle still lets the caller look at
+                // and clear those flags
+                Err(e) if e.downcast_ref::<Errno>() == Some(&Errno::EPERM) => (
+                    open_pinned(&dir, name, &pinned, OFlag::O_RDONLY, identity, path)?,
+                    false,
+                ),
+                Err(e) => return Err(e),
+            }
+        };
+
+        Ok(Self {
+            dir,
+            dir_path,
+            name: name.to_os_string(),
+            handle,
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            is_dir,
+            writable,
+        })
+    }
+
+    /// The open file or directory
+    pub fn file(&self) -> &File {
+        &self.handle
+    }
+
+    /// The directory holding the entry
+    pub fn dir(&self) -> &File {
+        &self.dir
+    }
+
+    pub fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    /// Whether `file()` was opened for writing
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Open the file for writing again, once whatever prevented it (such
+    /// as the immutable flag) is gone
+    pub fn reopen_writable(&mut self) -> Result<()> {
+        let path = self.path();
+        self.handle = open_pinned(
+            &self.dir,
+            &self.name,
+            &self.handle,
+            OFlag::O_RDWR,
+            (self.dev, self.ino),
+            &path,
+        )
+        .with_context(|| format!("Failed to open {} for writing", path.display()))?;
+        self.writable = true;
+        Ok(())
+    }
+
+    /// Device and inode numbers of the opened entry
+    pub fn identity(&self) -> (u64, u64) {
+        (self.dev, self.ino)
+    }
+
+    pub fn dir_path(&self) -> &Path {
+        &self.dir_path
+    }
+
+    /// Where the entry currently lives, for messages
+    pub fn path(&self) -> PathBuf {
+        self.dir_path.join(&self.name)
+    }
+
+    /// The kernel's view of the entry's absolute path, for looking up
+    /// mounts, layers and snapshots
+    pub fn canonical_path(&self) -> Option<PathBuf> {
+        std::fs::read_link(format!("/proc/self/fd/{}", self.handle.as_raw_fd())).ok()
+    }
+
+    /// Fail unless the entry's name still refers to the opened inode
+    pub fn verify(&self) -> Result<()> {
+        match fstatat(
+            self.dir.as_raw_fd(),
+            self.name.as_os_str(),
+            nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW,
+        ) {
+            Ok(stat) if (stat.st_dev, stat.st_ino) == (self.dev, self.ino) => Ok(()),
+            Ok(_) => bail!(
+                "{} was replaced by another file during the wipe; aborting",
+                self.path().display()
+            ),
+            Err(Errno::ENOENT) => bail!(
+                "{} was moved or removed by another process during the wipe; aborting",
+                self.path().display()
+            ),
+            Err(e) => Err(e).with_context(|| format!("Failed to stat {}", self.path().display())),
+        }
+    }
+
+    /// Rename the entry within its directory without replacing anything.
+    /// Returns false if `new_name` is taken.
+    pub fn rename_noreplace(&mut self, new_name: &OsStr) -> Result<bool> {
+        self.verify()?;
+        let dir = self.dir.as_raw_fd();
+        match renameat2(
+            Some(dir),
+            self.name.as_os_str(),
+            Some(dir),
+            new_name,
+            RenameFlags::RENAME_NOREPLACE,
+        ) {
+            Ok(()) => {}
+            Err(Errno::EEXIST) => return Ok(false),
+            // Filesystem without RENAME_NOREPLACE: check, then rename
+            Err(Errno::EINVAL) => {
+                let taken =
+                    fstatat(dir, new_name, nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW).is_ok();
+                if taken {
+                    return Ok(false);
+                }
+                renameat(Some(dir), self.name.as_os_str(), Some(dir), new_name)?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        self.name = new_name.to_os_string();
+        self.verify()?;
+        Ok(true)
+    }
+
+    /// Unlink the entry (rmdir for directories) once it is confirmed to be
+    /// the opened inode
+    pub fn remove(&self) -> Result<()> {
+        self.verify()?;
+        let flags = if self.is_dir {
+            UnlinkatFlags::RemoveDir
+        } else {
+            UnlinkatFlags::NoRemoveDir
+        };
+        unlinkat(Some(self.dir.as_raw_fd()), self.name.as_os_str(), flags)
+            .with_context(|| format!("Failed to remove {}", self.path().display()))?;
+        Ok(())
+    }
+
+    /// fsync the directory so renames and the unlink reach the disk
+    pub fn sync_dir(&self) -> Result<()> {
+        self.dir
+            .sync_all()
+            .with_context(|| format!("Failed to fsync directory {}", self.dir_path.display()))
+    }
+}
+
+/// Remove a symlink, FIFO, socket or device node by name, without
+/// following or opening it. Refuses if the name now holds a regular file or
+/// directory, which must go through the wiper. Returns what was removed.
+pub fn unlink_special(path: &Path) -> Result<&'static str> {
+    let Some(name) = path.file_name() else {
+        bail!("{} does not name a file", path.display());
+    };
+    let dir_path = super::sync::parent_dir(path);
+    let dir = open_directory(dir_path)?;
+
+    let stat = fstatat(
+        dir.as_raw_fd(),
+        name,
+        nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW,
+    )
+    .with_context(|| format!("Failed to stat {}", path.display()))?;
+    let kind = match SFlag::from_bits_truncate(stat.st_mode & SFlag::S_IFMT.bits()) {
+        SFlag::S_IFLNK => "symbolic link",
+        SFlag::S_IFIFO => "FIFO",
+        SFlag::S_IFSOCK => "socket",
+        SFlag::S_IFCHR | SFlag::S_IFBLK => "device node",
+        _ => bail!(
+            "{} was replaced by a regular file or directory; not removing it unwiped",
+            path.display()
+        ),
+    };
+
+    unlinkat(Some(dir.as_raw_fd()), name, UnlinkatFlags::NoRemoveDir)
+        .with_context(|| format!("Failed to remove {}", path.display()))?;
+    dir.sync_all()
+        .with_context(|| format!("Failed to fsync directory {}", dir_path.display()))?;
+    Ok(kind)
+}
+
+/// Open the directory at `path` one component at a time, refusing a
+/// symlink anywhere along the way, so a directory swapped for a link
+/// mid-run can't lead the wipe into another tree
+pub(crate) fn open_directory(path: &Path) -> Result<File> {
+    let start = if path.is_absolute() { "/" } else { "." };
+    let fd = open(
+        start,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+    .with_context(|| format!("Failed to open directory {}", start))?;
+    let mut dir = unsafe { File::from_raw_fd(fd) };
+
+    let components: Vec<_> = path
+        .components()
+        .filter(|c| !matches!(c, Component::RootDir | Component::CurDir))
+        .collect();
+    let mut walked = PathBuf::from(start);
+    for (i, component) in components.iter().enumerate() {
+        walked.push(component);
+        // Directories on the way are only looked through
+        let access = if i + 1 == components.len() {
+            OFlag::O_RDONLY
+        } else {
+            OFlag::O_PATH
+        };
+        let fd = match openat(
+            dir.as_raw_fd(),
+            component.as_os_str(),
+            access | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        ) {
+            Ok(fd) => fd,
+            // O_DIRECTORY|O_NOFOLLOW on a symlink fails with ENOTDIR
+            Err(Errno::ELOOP | Errno::ENOTDIR) if is_symlink_at(&dir, component.as_os_str()) => {
+                bail!(
+                    "{} is a symbolic link; not following it to reach {}",
+                    walked.display(),
+                    path.display()
+                )
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to open directory {}", walked.display()))
+            }
+        };
+        dir = unsafe { File::from_raw_fd(fd) };
+    }
+    Ok(dir)
+}
+
+fn is_symlink_at(dir: &File, name: &OsStr) -> bool {
+    fstatat(
+        dir.as_raw_fd(),
+        name,
+        nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW,
+    )
+    .is_ok_and(|stat| stat.st_mode & SFlag::S_IFMT.bits() == SFlag::S_IFLNK.bits())
+}
+
+/// Open the inode `pinned` refers to with `flags`, and make sure the result
+/// is that inode
+fn open_pinned(
+    dir: &File,
+    name: &OsStr,
+    pinned: &File,
+    flags: OFlag,
+    identity: (u64, u64),
+    path: &Path,
+) -> Result<File> {
+    let handle = reopen(pinned, flags).or_else(|_| {
+        // No /proc: open by name and check it's still the same inode below
+        let fd = openat(
+            dir.as_raw_fd(),
+            name,
+            flags | OFlag::O_NOFOLLOW | OFlag::O_NONBLOCK | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok::<_, anyhow::Error>(unsafe { File::from_raw_fd(fd) })
+    })?;
+
+    let opened = handle.metadata()?;
+    if (opened.dev(), opened.ino()) != identity {
+        bail!("{} was replaced while being opened", path.display());
+    }
+    Ok(handle)
+}
+
+/// Open the inode behind a descriptor for I/O through procfs,
+/// which can't be redirected the way a path lookup can
+fn reopen(pinned: &File, flags: OFlag) -> Result<File> {
+    let fd = open(
+        format!("/proc/self/fd/{}", pinned.as_raw_fd()).as_str(),
+        flags | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_refuses_symlinks_and_special_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let target = temp_dir.path().join("target");
+        let link = temp_dir.path().join("link");
+        std::fs::write(&target, b"data").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let error = PinnedEntry::open_file(&link).unwrap_err();
+        assert!(error.to_string().contains("symbolic link"));
+        assert!(PinnedEntry::open_file(temp_dir.path()).is_err());
+        assert!(PinnedEntry::open_dir(&target).is_err());
+        assert!(PinnedEntry::open_file(Path::new("/dev/null")).is_err());
+    }
+
+    #[test]
+    fn test_swapped_name_aborts_removal() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("secret");
+        let decoy = temp_dir.path().join("decoy");
+        std::fs::write(&path, b"secret").unwrap();
+        std::fs::write(&decoy, b"keep me").unwrap();
+
+        let entry = PinnedEntry::open_file(&path).unwrap();
+        // Someone replaces the name with a symlink to another file
+        std::fs::remove_file(&path).unwrap();
+        std::os::unix::fs::symlink(&decoy, &path).unwrap();
+
+        assert!(entry.verify().is_err());
+        assert!(entry.remove().is_err());
+        assert_eq!(std::fs::read(&decoy).unwrap(), b"keep me");
+        assert!(path.symlink_metadata().is_ok());
+    }
+
+    #[test]
+    fn test_unlink_special_leaves_targets_alone() {
+        let temp_dir = TempDir::new().unwrap();
+        let target = temp_dir.path().join("target");
+        let link = temp_dir.path().join("link");
+        let fifo = temp_dir.path().join("fifo");
+        std::fs::write(&target, b"data").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        nix::unistd::mkfifo(&fifo, Mode::S_IRUSR | Mode::S_IWUSR).unwrap();
+
+        assert_eq!(unlink_special(&link).unwrap(), "symbolic link");
+        assert_eq!(unlink_special(&fifo).unwrap(), "FIFO");
+        assert!(unlink_special(&target).is_err());
+        assert!(link.symlink_metadata().is_err());
+        assert!(fifo.symlink_metadata().is_err());
+        assert_eq!(std::fs::read(&target).unwrap(), b"data");
+
+        // A directory swapped for a symlink is not followed to the FIFO
+        let real = temp_dir.path().join("real");
+        let swapped = temp_dir.path().join("swapped");
+        std::fs::create_dir(&real).unwrap();
+        nix::unistd::mkfifo(&real.join("fifo"), Mode::S_IRUSR | Mode::S_IWUSR).unwrap();
+        std::os::unix::fs::symlink(&real, &swapped).unwrap();
+        let error = unlink_special(&swapped.join("fifo")).unwrap_err();
+        assert!(error.to_string().contains("symbolic link"));
+        assert!(real.join("fifo").symlink_metadata().is_ok());
+    }
+
+    #[test]
+    fn test_symlinked_parent_is_not_followed() {
+        let temp_dir = TempDir::new().unwrap();
+        let elsewhere = temp_dir.path().join("elsewhere");
+        std::fs::create_dir_all(elsewhere.join("sub")).unwrap();
+        std::fs::write(elsewhere.join("sub/file"), b"keep me").unwrap();
+        std::os::unix::fs::symlink(&elsewhere, temp_dir.path().join("link")).unwrap();
+
+        let through_link = temp_dir.path().join("link/sub/file");
+        let error = PinnedEntry::open_file(&through_link).unwrap_err();
+        assert!(error.to_string().contains("symbolic link"));
+        assert!(PinnedEntry::open_dir(&temp_dir.path().join("link/sub")).is_err());
+        assert!(unlink_special(&through_link).is_err());
+        assert_eq!(
+            std::fs::read(elsewhere.join("sub/file")).unwrap(),
+            b"keep me"
+        );
+    }
+
+    #[test]
+    fn test_rename_and_remove_follow_the_inode() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("file");
+        std::fs::write(&path, b"data").unwrap();
+        std::fs::write(temp_dir.path().join("taken"), b"other").unwrap();
+
+        let mut entry = PinnedEntry::open_file(&path).unwrap();
+        assert!(!entry.rename_noreplace(OsStr::new("taken")).unwrap());
+        assert!(entry.rename_noreplace(OsStr::new("fresh")).unwrap());
+        assert_eq!(entry.path(), temp_dir.path().join("fresh"));
+
+        let mut content = String::new();
+        entry.file().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "data");
+
+        entry.remove().unwrap();
+        entry.sync_dir().unwrap();
+        assert!(!temp_dir.path().join("fresh").exists());
+        assert_eq!(
+            std::fs::read(temp_dir.path().join("taken")).unwrap(),
+            b"other"
+        );
+    }
+}
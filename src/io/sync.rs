@@ -0,0 +1,168 @@
+use crate::filesystem::detector::detect_filesystem;
+use crate::filesystem::journal::{self, JournalCheckpoint};
+use crate::filesystem::FilesystemType;
+use crate::Result;
+use anyhow::Context;
+use nix::unistd::syncfs;
+use std::fs::{self, File};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Filesystems touched during a run, flushed with syncfs(2) once each at
+/// the end instead of syncing every mount on the machine per file
+#[derive(Debug, Default)]
+pub struct SyncBatch {
+    filesystems: Vec<SyncTarget>,
+}
+
+#[derive(Debug)]
+struct SyncTarget {
+    dev: u64,
+    /// Directory the handle was opened on
+    dir: PathBuf,
+    handle: File,
+    fs_type: FilesystemType,
+}
+
+/// What was done to one filesystem after all its files were wiped
+#[derive(Debug, Clone)]
+pub struct FilesystemOutcome {
+    /// A directory on the filesystem that held wiped files
+    pub dir: PathBuf,
+    pub fs_type: FilesystemType,
+    pub journal: JournalCheckpoint,
+}
+
+impl SyncBatch {
+    /// Remember the filesystem holding `path`. The handle is taken on the
+    /// parent directory, which outlives the file being wiped.
+    pub fn add(&mut self, path: &Path) -> Result<()> {
+        let dir = parent_dir(path);
+        let dev = fs::metadata(dir)
+            .with_context(|| format!("Failed to stat {}", dir.display()))?
+            .dev();
+        if self.filesystems.iter().any(|target| target.dev == dev) {
+            return Ok(());
+        }
+
+        let handle =
+            File::open(dir).with_context(|| format!("Failed to open {}", dir.display()))?;
+        let fs_type = detect_filesystem(dir)
+            .map(|info| info.fs_type)
+            .unwrap_or(FilesystemType::Unknown);
+        self.filesystems.push(SyncTarget {
+            dev,
+            dir: dir.to_path_buf(),
+            handle,
+            fs_type,
+        });
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.filesystems.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filesystems.is_empty()
+    }
+
+    /// syncfs every remembered filesystem, then clear journals that may
+    /// still hold old names or data. Fails if any syncfs failed.
+    pub fn sync(&mut self) -> Result<Vec<FilesystemOutcome>> {
+        let mut failures = Vec::new();
+        let mut outcomes = Vec::new();
+
+        for target in self.filesystems.drain(..) {
+            if let Err(e) = syncfs(target.handle.as_raw_fd()) {
+                failures.push(format!(
+                    "filesystem containing {}: {}",
+                    target.dir.display(),
+                    e
+                ));
+                continue;
+            }
+
+            let journal = match target.fs_type {
+                FilesystemType::Ext4 {
+                    has_journal: true, ..
+                } => {
+                    let result = journal::checkpoint_ext4(&target.handle);
+                    match &result {
+                        JournalCheckpoint::Failed(_) => {
+                            println!("⚠️  {}: {}", target.dir.display(), result);
+                            println!("   Note: Old names and data may remain in the journal until it wraps");
+                        }
+                        _ => println!("✅ {}: {}", target.dir.display(), result),
+                    }
+                    result
+                }
+                _ => JournalCheckpoint::NotApplicable,
+            };
+
+            outcomes.push(FilesystemOutcome {
+                dir: target.dir,
+                fs_type: target.fs_type,
+                journal,
+            });
+        }
+
+        if failures.is_empty() {
+            Ok(outcomes)
+        } else {
+            Err(anyhow::anyhow!("syncfs failed for {}", failures.join("; ")))
+        }
+    }
+}
+
+/// fsync the directory holding `path` so a rename or unlink in it is durable
+pub fn sync_parent_dir(path: &Path) -> Result<()> {
+    let dir = parent_dir(path);
+    File::open(dir)
+        .and_then(|handle| handle.sync_all())
+        .with_context(|| format!("Failed to fsync directory {}", dir.display()))
+}
+
+pub(crate) fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_batch_holds_one_handle_per_filesystem() -> Result<()> {
+        let dir = tempdir()?;
+        let first = dir.path().join("a");
+        let second = dir.path().join("b");
+        fs::write(&first, b"a")?;
+        fs::write(&second, b"b")?;
+
+        let mut batch = SyncBatch::default();
+        batch.add(&first)?;
+        batch.add(&second)?;
+        assert_eq!(batch.len(), 1);
+
+        // The file may be gone by the time the batch is flushed
+        fs::remove_file(&first)?;
+        sync_parent_dir(&first)?;
+        let outcomes = batch.sync()?;
+        assert!(batch.is_empty());
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].dir, dir.path());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_path_uses_current_dir() {
+        assert_eq!(parent_dir(Path::new("file.txt")), Path::new("."));
+        assert_eq!(parent_dir(Path::new("dir/file.txt")), Path::new("dir"));
+    }
+}
@@ -4,11 +4,46 @@ pub mod io;
 pub mod patterns;
 pub mod security;
 pub mod storage;
+pub mod sysroot;
 
 pub use anyhow::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
 pub use std::path::{Path, PathBuf};
 use tokio::fs;
 
+/// The target cannot be meaningfully wiped where it lives (read-only
+/// mount, data on another machine, ...). Kept distinct from I/O failures
+/// so callers can tell "not wipeable here" from "wipe failed".
+#[derive(Debug)]
+pub struct NotWipeable {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl NotWipeable {
+    pub fn new(path: &Path, reason: impl Into<String>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for NotWipeable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} cannot be wiped: {}",
+            self.path.display(),
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for NotWipeable {}
+
 #[derive(Debug, Clone)]
 pub struct AmaterasuConfig {
     pub verify: bool,
@@ -17,6 +52,23 @@ pub struct AmaterasuConfig {
     pub mode: WipeMode,
     pub wipe_metadata: bool,
     pub metadata_passes: usize,
+    pub device_policies: BTreeMap<String, config::DeviceOverride>,
+    pub shared_extents: SharedExtentPolicy,
+    /// List the snapshots that must be deleted for each file's data to be gone
+    pub snapshot_report: bool,
+    /// Wipe files on network and FUSE filesystems instead of refusing
+    pub allow_remote: bool,
+    /// Print the key identifiers protecting encrypted files
+    pub encryption_report: bool,
+    /// Set wiped files' timestamps to this many seconds since the epoch
+    /// instead of random times
+    pub fixed_timestamp: Option<i64>,
+    /// Wipe what symlinks point to instead of only removing the links
+    pub follow_symlinks: bool,
+    pub hardlinks: HardlinkPolicy,
+    pub special_files: SpecialFilePolicy,
+    /// Clear immutable and append-only attributes instead of refusing
+    pub clear_immutable: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -26,6 +78,35 @@ pub enum WipeMode {
     Paranoid,
 }
 
+/// What to do with a file whose extents are shared with other files or
+/// snapshots; overwriting it only gives it private copies
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SharedExtentPolicy {
+    #[default]
+    Warn,
+    Refuse,
+}
+
+/// What to do with a file that has more than one name; wiping it destroys
+/// the content behind every name
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HardlinkPolicy {
+    #[default]
+    Refuse,
+    /// Wipe it and remove every name of it found among the targets
+    WipeAll,
+}
+
+/// What to do with FIFOs, sockets and device nodes, which hold no data of
+/// their own to overwrite
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SpecialFilePolicy {
+    #[default]
+    Skip,
+    Unlink,
+}
+
 impl Default for AmaterasuConfig {
     fn default() -> Self {
         Self {
@@ -35,10 +116,114 @@ impl Default for AmaterasuConfig {
             mode: WipeMode::Standard,
             wipe_metadata: true,
             metadata_passes: 3,
+            device_policies: BTreeMap::new(),
+            shared_extents: SharedExtentPolicy::Warn,
+            snapshot_report: false,
+            allow_remote: false,
+            encryption_report: false,
+            fixed_timestamp: None,
+            follow_symlinks: false,
+            hardlinks: HardlinkPolicy::Refuse,
+            special_files: SpecialFilePolicy::Skip,
+            clear_immutable: false,
         }
     }
 }
 
+/// Targets collected so far, with the inodes already seen
+#[derive(Default)]
+struct Collection {
+    targets: WipeTargets,
+    /// The name in `targets.files` of each inode
+    files_seen: HashMap<(u64, u64), PathBuf>,
+    directories_seen: HashSet<(u64, u64)>,
+}
+
+impl Collection {
+    /// Add a regular file, recording further names of an inode already
+    /// collected as hard links instead of wiping it twice
+    fn add_file(&mut self, path: PathBuf, metadata: &std::fs::Metadata) {
+        let (dev, ino) = (metadata.dev(), metadata.ino());
+        let Some(first) = self.files_seen.get(&(dev, ino)) else {
+            self.files_seen.insert((dev, ino), path.clone());
+            self.targets.files.push(path);
+            return;
+        };
+        if metadata.nlink() <= 1 {
+            // The same name reached twice, through a followed symlink
+            return;
+        }
+
+        let links = self
+            .targets
+            .hard_links
+            .entry(first.clone())
+            .or_insert_with(|| HardLinks {
+                dev,
+                ino,
+                names: Vec::new(),
+            });
+        let canonical = |name: &Path| std::fs::canonicalize(name).ok();
+        let known = std::iter::once(first)
+            .chain(&links.names)
+            .any(|name| canonical(name) == canonical(&path));
+        if !known {
+            links.names.push(path);
+        }
+    }
+}
+
+fn special_kind(file_type: &std::fs::FileType) -> &'static str {
+    if file_type.is_fifo() {
+        "FIFO"
+    } else if file_type.is_socket() {
+        "socket"
+    } else if file_type.is_char_device() || file_type.is_block_device() {
+        "device node"
+    } else {
+        "special file"
+    }
+}
+
+/// Resolve symlinks in the directories of an operand now, as the user
+/// named them; the wipe itself refuses to pass through any. Operands whose
+/// directories hold no symlink are kept as given.
+async fn resolve_parent(path: &Path) -> PathBuf {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return path.to_path_buf();
+    };
+    if parent.as_os_str().is_empty() || io::pinned::open_directory(parent).is_ok() {
+        return path.to_path_buf();
+    }
+    match fs::canonicalize(parent).await {
+        Ok(parent) => parent.join(name),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// What a run will delete: files to wipe, then directories to remove
+#[derive(Debug, Clone, Default)]
+pub struct WipeTargets {
+    pub files: Vec<PathBuf>,
+    /// Other names of hard-linked files, keyed by the name in `files`
+    pub hard_links: BTreeMap<PathBuf, HardLinks>,
+    /// Symlinks and special files, removed without being followed or opened
+    pub unlink: Vec<PathBuf>,
+    /// Deepest first
+    pub directories: Vec<PathBuf>,
+    /// Also remove directories of wiped files that the wipe leaves empty
+    pub remove_empty_parents: bool,
+}
+
+/// Names of one hard-linked file found among the targets besides the one
+/// being wiped
+#[derive(Debug, Clone)]
+pub struct HardLinks {
+    pub dev: u64,
+    pub ino: u64,
+    pub names: Vec<PathBuf>,
+}
+
 pub struct Amaterasu {
     config: AmaterasuConfig,
 }
@@ -48,112 +233,810 @@ impl Amaterasu {
         Self { config }
     }
 
-    pub async fn wipe_file(&self, path: &Path) -> Result<()> {
-        let storage_type = storage::detector::detect_storage_type(path)?;
+    pub async fn wipe_file(&self, path: &Path) -> Result<io::WipeOutcome> {
+        let mut syncs = io::sync::SyncBatch::default();
+        let outcome = self.wipe_file_batched(path, 0, &mut syncs).await?;
+        syncs.sync()?;
+        Ok(outcome)
+    }
+
+    /// Wipe one file, leaving the syncfs of its filesystem to the caller.
+    /// `known_links` is how many of its other names the run removes.
+    async fn wipe_file_batched(
+        &self,
+        path: &Path,
+        known_links: usize,
+        syncs: &mut io::sync::SyncBatch,
+    ) -> Result<io::WipeOutcome> {
+        syncs.add(path)?;
+        let device = storage::detector::detect_block_device(path)?;
+        let (storage_type, passes) = match device {
+            Some(device) => self.apply_device_policy(device),
+            None => (
+                storage::detector::detect_memory_storage(path)
+                    .unwrap_or(storage::StorageType::Unknown),
+                None,
+            ),
+        };
         let pattern_generator = patterns::create_random_generator();
-        let wiper = io::FileWiper::new(&storage_type, self.config.clone());
+        let wiper = io::FileWiper::new(&storage_type, self.config.clone())
+            .with_passes(passes)
+            .with_known_links(known_links);
 
         wiper.wipe(path, pattern_generator).await
     }
 
+    /// Apply the first matching `[devices]` entry to the detected device,
+    /// returning the storage type to use and an optional pass count.
+    fn apply_device_policy(
+        &self,
+        device: storage::detector::BlockDevice,
+    ) -> (storage::StorageType, Option<usize>) {
+        let Some((label, policy)) = self
+            .config
+            .device_policies
+            .iter()
+            .find(|(_, policy)| policy.matches(&device))
+        else {
+            return (device.storage_type, None);
+        };
+
+        let storage_type = match policy.storage.as_deref() {
+            Some(storage_str) => {
+                config::parse_storage_type_from_config(storage_str, &device.storage_type)
+                    .unwrap_or_else(|| {
+                        eprintln!(
+                            "Warning: Unknown storage type '{}' in device policy '{}', keeping detected type",
+                            storage_str, label
+                        );
+                        device.storage_type.clone()
+                    })
+            }
+            None => device.storage_type.clone(),
+        };
+
+        println!("Device policy '{}' applies to {}", label, device.name);
+        (storage_type, policy.passes)
+    }
+
     pub async fn collect_files(&self, paths: &[PathBuf], recursive: bool) -> Result<Vec<PathBuf>> {
-        let mut files_to_wipe = Vec::new();
+        Ok(self.collect_targets(paths, recursive).await?.files)
+    }
+
+    /// Expand `paths` into the files to wipe and, in recursive mode, the
+    /// directories to remove afterwards, deepest first. Symlinks are never
+    /// wiped through unless `follow_symlinks` is set; the links themselves
+    /// are removed either way.
+    pub async fn collect_targets(&self, paths: &[PathBuf], recursive: bool) -> Result<WipeTargets> {
+        let mut collection = Collection::default();
 
         for path in paths {
-            if path.is_file() {
-                files_to_wipe.push(path.clone());
-            } else if path.is_dir() {
-                if recursive {
-                    let dir_files = self.collect_files_from_directory(path).await?;
-                    files_to_wipe.extend(dir_files);
-                } else if !self.config.force {
-                    eprintln!("Warning: {} is a directory. Use -r/--recursive to delete directories and their contents.", path.display());
+            let path = &resolve_parent(path).await;
+            let Ok(metadata) = fs::symlink_metadata(path).await else {
+                if !self.config.force {
+                    eprintln!(
+                        "Warning: {} does not exist or is not a regular file/directory.",
+                        path.display()
+                    );
                 }
-            } else if !self.config.force {
-                eprintln!(
-                    "Warning: {} does not exist or is not a regular file/directory.",
-                    path.display()
-                );
+                continue;
+            };
+            if let Some(dir) = self
+                .collect_entry(path, metadata, recursive, &mut collection)
+                .await?
+            {
+                self.collect_from_directory(&dir, &mut collection).await?;
             }
         }
 
-        Ok(files_to_wipe)
+        let mut targets = collection.targets;
+        // Children before parents, so each is empty when its turn comes
+        targets.directories.sort_by(|a, b| {
+            let depth = |dir: &PathBuf| dir.components().count();
+            depth(b).cmp(&depth(a)).then_with(|| a.cmp(b))
+        });
+        targets.directories.dedup();
+        targets.unlink.sort();
+        targets.unlink.dedup();
+
+        Ok(targets)
     }
 
-    async fn collect_files_from_directory(&self, dir_path: &Path) -> Result<Vec<PathBuf>> {
-        let mut files = Vec::new();
+    async fn collect_from_directory(
+        &self,
+        dir_path: &Path,
+        collection: &mut Collection,
+    ) -> Result<()> {
         let mut stack = vec![dir_path.to_path_buf()];
 
         while let Some(current_dir) = stack.pop() {
             let mut entries = fs::read_dir(&current_dir).await?;
+            collection.targets.directories.push(current_dir);
 
             while let Some(entry) = entries.next_entry().await? {
-                let path = entry.path();
+                // Not following symlinks
                 let metadata = entry.metadata().await?;
+                if let Some(dir) = self
+                    .collect_entry(&entry.path(), metadata, true, collection)
+                    .await?
+                {
+                    stack.push(dir);
+                }
+            }
+        }
 
-                if metadata.is_file() {
-                    files.push(path);
-                } else if metadata.is_dir() {
-                    stack.push(path);
+        Ok(())
+    }
+
+    /// Sort one entry, described by its lstat metadata, into the targets.
+    /// Returns a directory still to be descended into.
+    async fn collect_entry(
+        &self,
+        path: &Path,
+        metadata: std::fs::Metadata,
+        recursive: bool,
+        collection: &mut Collection,
+    ) -> Result<Option<PathBuf>> {
+        let (path, metadata) = if metadata.file_type().is_symlink() {
+            collection.targets.unlink.push(path.to_path_buf());
+            if !self.config.follow_symlinks {
+                return Ok(None);
+            }
+            match fs::canonicalize(path).await {
+                Ok(target) => {
+                    let metadata = fs::metadata(&target).await?;
+                    (target, metadata)
+                }
+                Err(_) => {
+                    eprintln!(
+                        "Warning: {} is a dangling symbolic link; removing only the link",
+                        path.display()
+                    );
+                    return Ok(None);
+                }
+            }
+        } else {
+            (path.to_path_buf(), metadata)
+        };
+
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            if !recursive {
+                if !self.config.force {
+                    eprintln!("Warning: {} is a directory. Use -r/--recursive to delete directories and their contents.", path.display());
                 }
+                return Ok(None);
             }
+            // A followed symlink may lead back into the tree
+            let first_visit = collection
+                .directories_seen
+                .insert((metadata.dev(), metadata.ino()));
+            return Ok(first_visit.then_some(path));
+        }
+
+        if file_type.is_file() {
+            collection.add_file(path, &metadata);
+            return Ok(None);
         }
 
-        Ok(files)
+        match self.config.special_files {
+            SpecialFilePolicy::Unlink => collection.targets.unlink.push(path),
+            SpecialFilePolicy::Skip => eprintln!(
+                "Warning: Skipping {} {} (use --special-files unlink to remove it)",
+                special_kind(&file_type),
+                path.display()
+            ),
+        }
+        Ok(None)
     }
 
-    pub async fn wipe_files(&self, paths: &[PathBuf]) -> Result<()> {
-        for path in paths {
-            if let Err(e) = self.wipe_file(path).await {
+    /// Wipe `paths`, then remove the directories holding them that the
+    /// wipe left empty
+    pub async fn wipe_files(&self, paths: &[PathBuf]) -> Result<io::WipeReport> {
+        let targets = WipeTargets {
+            files: paths.to_vec(),
+            remove_empty_parents: true,
+            ..WipeTargets::default()
+        };
+        self.wipe_targets(&targets).await
+    }
+
+    /// Wipe every file, then rename and remove the directories, and with
+    /// `remove_empty_parents` the emptied directories of the files
+    pub async fn wipe_targets(&self, targets: &WipeTargets) -> Result<io::WipeReport> {
+        let mut report = io::WipeReport::default();
+        let mut syncs = io::sync::SyncBatch::default();
+
+        for path in &targets.files {
+            let links = targets.hard_links.get(path);
+            let known_links = links.map_or(0, |links| links.names.len());
+            match self.wipe_file_batched(path, known_links, &mut syncs).await {
+                Ok(outcome) => {
+                    report.files.push(outcome);
+                    if let Some(links) = links {
+                        self.remove_hard_links(links, &mut report).await?;
+                    }
+                }
+                Err(e) => {
+                    if !self.config.force {
+                        return Err(e);
+                    }
+                    if e.downcast_ref::<NotWipeable>().is_some() {
+                        eprintln!("Warning: Skipping {}", e);
+                        report.refused.push(path.clone());
+                    } else {
+                        eprintln!("Warning: Failed to wipe {}: {}", path.display(), e);
+                        report.failed.push(path.clone());
+                    }
+                }
+            }
+        }
+
+        for path in &targets.unlink {
+            match Self::remove_special(path, &mut syncs) {
+                Ok(()) => report.unlinked.push(path.clone()),
+                Err(e) => {
+                    if !self.config.force {
+                        return Err(e);
+                    }
+                    eprintln!("Warning: Failed to remove {}: {}", path.display(), e);
+                    report.failed.push(path.clone());
+                }
+            }
+        }
+
+        for dir in &targets.directories {
+            match self.remove_directory(dir, &mut syncs).await {
+                Ok(true) => report.directories.push(dir.clone()),
+                Ok(false) => {}
+                Err(e) => {
+                    if !self.config.force {
+                        return Err(e);
+                    }
+                    eprintln!(
+                        "Warning: Failed to remove directory {}: {}",
+                        dir.display(),
+                        e
+                    );
+                    report.failed.push(dir.clone());
+                }
+            }
+        }
+
+        if targets.remove_empty_parents {
+            self.remove_empty_parents(&report.files, &mut report.directories, &mut syncs)
+                .await;
+        }
+
+        // One syncfs per filesystem touched, covering data, renames and unlinks
+        if !syncs.is_empty() {
+            println!("🔄 Syncing {} filesystem(s)...", syncs.len());
+        }
+        match syncs.sync() {
+            Ok(filesystems) => report.filesystems = filesystems,
+            Err(e) => {
                 if self.config.force {
-                    eprintln!("Warning: Failed to wipe {}: {}", path.display(), e);
+                    eprintln!("Warning: {}", e);
+                    report.sync_failed = true;
                 } else {
                     return Err(e);
                 }
             }
         }
 
-        // After wiping all files, remove empty directories if any were processed
-        if let Err(e) = self.cleanup_empty_directories(paths).await {
-            if !self.config.force {
-                return Err(e);
+        Ok(report)
+    }
+
+    /// Remove the other names of a wiped file found among the targets
+    async fn remove_hard_links(
+        &self,
+        links: &HardLinks,
+        report: &mut io::WipeReport,
+    ) -> Result<()> {
+        for name in &links.names {
+            match self.remove_hard_link(name, links).await {
+                Ok(()) => report.unlinked.push(name.clone()),
+                Err(e) => {
+                    if !self.config.force {
+                        return Err(e);
+                    }
+                    eprintln!(
+                        "Warning: Failed to remove hard link {}: {}",
+                        name.display(),
+                        e
+                    );
+                    report.failed.push(name.clone());
+                }
             }
         }
+        Ok(())
+    }
+
+    /// Remove another name of a wiped file, renaming it first like the
+    /// wiped name, provided it still refers to the same inode
+    async fn remove_hard_link(&self, name: &Path, links: &HardLinks) -> Result<()> {
+        let entry = io::pinned::PinnedEntry::open_file(name)?;
+        if entry.identity() != (links.dev, links.ino) {
+            anyhow::bail!(
+                "{} no longer refers to the wiped file; leaving it",
+                name.display()
+            );
+        }
+
+        if self.config.wipe_metadata {
+            let name_rules = filesystem::detector::detect_filesystem_of(entry.file(), name)
+                .ok()
+                .and_then(|info| info.get_optimizer().name_rules());
+            security::metadata::MetadataWiper::new(self.config.metadata_passes)
+                .with_name_rules(name_rules)
+                .with_fixed_timestamp(self.config.fixed_timestamp)
+                .wipe_pinned(entry)
+                .await?;
+        } else {
+            entry.remove()?;
+            entry.sync_dir()?;
+        }
 
+        println!("Removed hard link: {}", name.display());
         Ok(())
     }
 
-    async fn cleanup_empty_directories(&self, paths: &[PathBuf]) -> Result<()> {
-        let mut dirs_to_remove = std::collections::HashSet::new();
+    /// Remove a symlink or special file without following or opening it
+    fn remove_special(path: &Path, syncs: &mut io::sync::SyncBatch) -> Result<()> {
+        syncs.add(path)?;
+        let kind = io::pinned::unlink_special(path)?;
+        println!("Removed {}: {}", kind, path.display());
+        Ok(())
+    }
 
-        // Collect all parent directories of wiped files
-        for path in paths {
-            if let Some(parent) = path.parent() {
-                dirs_to_remove.insert(parent.to_path_buf());
+    /// Remove the directories of wiped files that are now empty, deepest
+    /// first. Failures only warn: the files themselves are gone.
+    async fn remove_empty_parents(
+        &self,
+        wiped: &[io::WipeOutcome],
+        removed: &mut Vec<PathBuf>,
+        syncs: &mut io::sync::SyncBatch,
+    ) {
+        let mut parents: Vec<&Path> = wiped
+            .iter()
+            .filter_map(|outcome| outcome.path.parent())
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .collect();
+        parents.sort_by_key(|dir| (std::cmp::Reverse(dir.components().count()), *dir));
+        parents.dedup();
+
+        for dir in parents {
+            let Ok(mut entries) = fs::read_dir(dir).await else {
+                continue;
+            };
+            if !matches!(entries.next_entry().await, Ok(None)) {
+                continue;
+            }
+            match self.remove_directory(dir, syncs).await {
+                Ok(true) => removed.push(dir.to_path_buf()),
+                Ok(false) => {}
+                Err(e) => eprintln!(
+                    "Warning: Could not remove empty directory {}: {}",
+                    dir.display(),
+                    e
+                ),
             }
         }
+    }
 
-        // Sort directories by depth (deepest first) to remove them bottom-up
-        let mut sorted_dirs: Vec<_> = dirs_to_remove.into_iter().collect();
-        sorted_dirs.sort_by(|a, b| b.components().count().cmp(&a.components().count()));
+    /// Rename an emptied directory with the metadata wiper and remove it.
+    /// Returns false, leaving it in place, if something is still inside
+    /// (a file that failed or was refused, a symlink, a socket, ...).
+    async fn remove_directory(&self, dir: &Path, syncs: &mut io::sync::SyncBatch) -> Result<bool> {
+        let mut entries = fs::read_dir(dir).await?;
+        if entries.next_entry().await?.is_some() {
+            eprintln!("Warning: Keeping non-empty directory {}", dir.display());
+            return Ok(false);
+        }
 
-        for dir in sorted_dirs {
-            if let Ok(mut entries) = fs::read_dir(&dir).await {
-                if entries.next_entry().await?.is_none() {
-                    // Directory is empty, remove it
-                    if let Err(e) = fs::remove_dir(&dir).await {
-                        eprintln!(
-                            "Warning: Could not remove empty directory {}: {}",
-                            dir.display(),
-                            e
-                        );
-                    } else {
-                        println!("Removed empty directory: {}", dir.display());
-                    }
-                }
-            }
+        let entry = io::pinned::PinnedEntry::open_dir(dir)?;
+        let clear = self.config.clear_immutable;
+        let mut own_flags = io::check_protection(dir, dir, entry.file(), clear)?;
+        // Put back on the parent once the directory is gone, or on error
+        let _parent_flags = io::check_protection(dir, entry.dir_path(), entry.dir(), clear)?;
+        syncs.add(dir)?;
+        if self.config.wipe_metadata {
+            let name_rules = filesystem::detector::detect_filesystem_of(entry.file(), dir)
+                .ok()
+                .and_then(|info| info.get_optimizer().name_rules());
+            security::metadata::MetadataWiper::new(self.config.metadata_passes)
+                .with_name_rules(name_rules)
+                .with_fixed_timestamp(self.config.fixed_timestamp)
+                .wipe_pinned(entry)
+                .await?;
+        } else {
+            entry.remove()?;
+            entry.sync_dir()?;
         }
+        own_flags.keep_cleared();
 
-        Ok(())
+        println!("Removed directory: {}", dir.display());
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_recursive_wipe_removes_directories() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("project");
+        std::fs::create_dir_all(root.join("src/nested")).unwrap();
+        std::fs::create_dir_all(root.join("empty/deeper")).unwrap();
+        std::fs::write(root.join("src/main.rs"), b"fn main() {}").unwrap();
+        std::fs::write(root.join("README"), b"readme").unwrap();
+
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            verify: false,
+            ..AmaterasuConfig::default()
+        });
+        let targets = amaterasu
+            .collect_targets(std::slice::from_ref(&root), true)
+            .await
+            .unwrap();
+        assert_eq!(targets.files.len(), 2);
+        assert_eq!(targets.directories.len(), 5);
+        assert_eq!(targets.directories.last(), Some(&root));
+
+        let report = amaterasu.wipe_targets(&targets).await.unwrap();
+        assert_eq!(report.directories.len(), 5);
+        assert!(!root.exists());
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_non_empty_directory_is_kept() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("data");
+        std::fs::create_dir(&root).unwrap();
+        let fifo = root.join("fifo");
+        nix::unistd::mkfifo(&fifo, nix::sys::stat::Mode::S_IRWXU).unwrap();
+
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            ..AmaterasuConfig::default()
+        });
+        let targets = amaterasu
+            .collect_targets(std::slice::from_ref(&root), true)
+            .await
+            .unwrap();
+        let report = amaterasu.wipe_targets(&targets).await.unwrap();
+        assert!(report.directories.is_empty());
+        assert!(fifo.symlink_metadata().is_ok());
+
+        // Unlinked on request, which empties the directory
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            special_files: SpecialFilePolicy::Unlink,
+            ..AmaterasuConfig::default()
+        });
+        let targets = amaterasu
+            .collect_targets(std::slice::from_ref(&root), true)
+            .await
+            .unwrap();
+        let report = amaterasu.wipe_targets(&targets).await.unwrap();
+        assert_eq!(report.unlinked, vec![fifo]);
+        assert!(!root.exists());
+    }
+
+    #[tokio::test]
+    async fn test_symlinks_are_removed_not_followed() {
+        let temp_dir = TempDir::new().unwrap();
+        let outside = temp_dir.path().join("outside.txt");
+        let root = temp_dir.path().join("data");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(&outside, b"keep me").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("file-link")).unwrap();
+        std::os::unix::fs::symlink(temp_dir.path(), root.join("dir-link")).unwrap();
+
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            ..AmaterasuConfig::default()
+        });
+        let targets = amaterasu
+            .collect_targets(std::slice::from_ref(&root), true)
+            .await
+            .unwrap();
+        assert!(targets.files.is_empty());
+        assert_eq!(targets.unlink.len(), 2);
+
+        let report = amaterasu.wipe_targets(&targets).await.unwrap();
+        assert_eq!(report.unlinked.len(), 2);
+        assert!(!root.exists());
+        assert_eq!(std::fs::read(&outside).unwrap(), b"keep me");
+
+        // Followed on request: the target is wiped and the link removed
+        let link = temp_dir.path().join("link");
+        std::os::unix::fs::symlink(&outside, &link).unwrap();
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            follow_symlinks: true,
+            ..AmaterasuConfig::default()
+        });
+        let targets = amaterasu
+            .collect_targets(std::slice::from_ref(&link), false)
+            .await
+            .unwrap();
+        assert_eq!(
+            targets.files,
+            vec![std::fs::canonicalize(&outside).unwrap()]
+        );
+        amaterasu.wipe_targets(&targets).await.unwrap();
+        assert!(!outside.exists());
+        assert!(link.symlink_metadata().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parent_swapped_for_symlink_after_collection() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("shared");
+        let elsewhere = temp_dir.path().join("elsewhere");
+        std::fs::create_dir_all(root.join("dir")).unwrap();
+        std::fs::create_dir_all(elsewhere.join("dir")).unwrap();
+        std::fs::write(root.join("dir/file"), b"secret").unwrap();
+        std::fs::write(elsewhere.join("dir/file"), b"keep me").unwrap();
+
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            force: true,
+            ..AmaterasuConfig::default()
+        });
+        let targets = amaterasu
+            .collect_targets(std::slice::from_ref(&root), true)
+            .await
+            .unwrap();
+        assert_eq!(targets.files, vec![root.join("dir/file")]);
+
+        // Someone replaces the tree with a link to another one
+        std::fs::rename(&root, temp_dir.path().join("moved")).unwrap();
+        std::os::unix::fs::symlink(&elsewhere, &root).unwrap();
+
+        let report = amaterasu.wipe_targets(&targets).await.unwrap();
+        assert!(report.files.is_empty());
+        assert!(report.directories.is_empty());
+        assert_eq!(report.failed, vec![root.join("dir/file")]);
+        assert_eq!(
+            std::fs::read(elsewhere.join("dir/file")).unwrap(),
+            b"keep me"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_symlinked_operand_directory_is_resolved() {
+        let temp_dir = TempDir::new().unwrap();
+        let real = temp_dir.path().join("real");
+        std::fs::create_dir(&real).unwrap();
+        std::fs::write(real.join("file"), b"secret").unwrap();
+        std::os::unix::fs::symlink(&real, temp_dir.path().join("link")).unwrap();
+
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            ..AmaterasuConfig::default()
+        });
+        let targets = amaterasu
+            .collect_targets(&[temp_dir.path().join("link/file")], false)
+            .await
+            .unwrap();
+        let real = std::fs::canonicalize(&real).unwrap();
+        assert_eq!(targets.files, vec![real.join("file")]);
+        amaterasu.wipe_targets(&targets).await.unwrap();
+        assert!(!real.join("file").exists());
+    }
+
+    #[tokio::test]
+    async fn test_hard_links() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("data");
+        let outside = temp_dir.path().join("outside");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("a"), b"shared content").unwrap();
+        std::fs::hard_link(root.join("a"), root.join("b")).unwrap();
+        std::fs::hard_link(root.join("a"), &outside).unwrap();
+
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            ..AmaterasuConfig::default()
+        });
+        let targets = amaterasu
+            .collect_targets(std::slice::from_ref(&root), true)
+            .await
+            .unwrap();
+        assert_eq!(targets.files.len(), 1);
+        assert_eq!(targets.hard_links[&targets.files[0]].names.len(), 1);
+
+        let error = amaterasu.wipe_targets(&targets).await.unwrap_err();
+        assert!(error.downcast_ref::<NotWipeable>().is_some());
+        assert_eq!(std::fs::read(&outside).unwrap(), b"shared content");
+
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            hardlinks: HardlinkPolicy::WipeAll,
+            ..AmaterasuConfig::default()
+        });
+        let report = amaterasu.wipe_targets(&targets).await.unwrap();
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(report.unlinked.len(), 1);
+        assert!(!root.exists());
+        // A name outside the targets keeps the emptied inode
+        assert_eq!(std::fs::metadata(&outside).unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_wipe_files_removes_emptied_parents() {
+        let temp_dir = TempDir::new().unwrap();
+        let emptied = temp_dir.path().join("emptied");
+        let kept = temp_dir.path().join("kept");
+        std::fs::create_dir(&emptied).unwrap();
+        std::fs::create_dir(&kept).unwrap();
+        std::fs::write(emptied.join("a"), b"a").unwrap();
+        std::fs::write(kept.join("b"), b"b").unwrap();
+        std::fs::write(kept.join("other"), b"other").unwrap();
+
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            ..AmaterasuConfig::default()
+        });
+        let report = amaterasu
+            .wipe_files(&[emptied.join("a"), kept.join("b")])
+            .await
+            .unwrap();
+        assert_eq!(report.directories, vec![emptied.clone()]);
+        assert!(!emptied.exists());
+        assert!(kept.join("other").exists());
+    }
+
+    #[tokio::test]
+    async fn test_collected_targets_can_remove_emptied_parents() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("dir");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("file"), b"secret").unwrap();
+
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            ..AmaterasuConfig::default()
+        });
+        let mut targets = amaterasu
+            .collect_targets(&[dir.join("file")], false)
+            .await
+            .unwrap();
+        targets.remove_empty_parents = true;
+        let report = amaterasu.wipe_targets(&targets).await.unwrap();
+        assert_eq!(report.directories, vec![dir.clone()]);
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_force_records_failures_apart_from_refusals() {
+        let temp_dir = TempDir::new().unwrap();
+        let linked = temp_dir.path().join("linked");
+        let missing = temp_dir.path().join("missing");
+        std::fs::write(&linked, b"data").unwrap();
+        std::fs::hard_link(&linked, temp_dir.path().join("other")).unwrap();
+
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            force: true,
+            ..AmaterasuConfig::default()
+        });
+        let report = amaterasu
+            .wipe_files(&[missing.clone(), linked.clone()])
+            .await
+            .unwrap();
+        assert_eq!(report.failed, vec![missing]);
+        assert_eq!(report.refused, vec![linked]);
+        assert!(report.has_failures());
+    }
+
+    #[test]
+    fn test_protection_refusal() {
+        use filesystem::flags::{FS_APPEND_FL, FS_IMMUTABLE_FL, FS_NOCOW_FL};
+
+        let path = Path::new("/data/secret");
+        let refusal = io::protection_refusal(path, path, FS_IMMUTABLE_FL).unwrap();
+        let message = refusal.to_string();
+        assert!(message.contains("it is immutable (chattr +i)"));
+        assert!(message.contains("--clear-immutable"));
+        let error = anyhow::Error::from(refusal);
+        assert!(error.downcast_ref::<NotWipeable>().is_some());
+
+        let refusal =
+            io::protection_refusal(path, Path::new("/data"), FS_IMMUTABLE_FL | FS_APPEND_FL)
+                .unwrap();
+        assert!(refusal
+            .to_string()
+            .contains("its directory /data is immutable and append-only (chattr +ia)"));
+
+        assert!(io::protection_refusal(path, path, 0).is_none());
+        assert!(io::protection_refusal(path, path, FS_NOCOW_FL).is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs CAP_LINUX_IMMUTABLE and a filesystem with inode flags"]
+    async fn test_immutable_and_append_only_files() {
+        use filesystem::flags::{self, FS_APPEND_FL, FS_IMMUTABLE_FL};
+
+        let temp_dir = TempDir::new().unwrap();
+        for (flag, attribute) in [
+            (FS_IMMUTABLE_FL, "immutable"),
+            (FS_APPEND_FL, "append-only"),
+        ] {
+            let path = temp_dir.path().join(attribute);
+            std::fs::write(&path, b"secret").unwrap();
+            let file = std::fs::File::open(&path).unwrap();
+            let current = flags::get_flags(&file).unwrap();
+            flags::set_flags(&file, current | flag).unwrap();
+
+            let amaterasu = Amaterasu::new(AmaterasuConfig {
+                progress: false,
+                ..AmaterasuConfig::default()
+            });
+            let error = amaterasu.wipe_file(&path).await.unwrap_err();
+            assert!(error.downcast_ref::<NotWipeable>().is_some());
+            assert!(error.to_string().contains(attribute));
+            assert_eq!(std::fs::read(&path).unwrap(), b"secret");
+
+            let amaterasu = Amaterasu::new(AmaterasuConfig {
+                progress: false,
+                clear_immutable: true,
+                ..AmaterasuConfig::default()
+            });
+            let outcome = amaterasu.wipe_file(&path).await.unwrap();
+            assert_eq!(outcome.cleared_attributes.len(), 1);
+            assert_eq!(outcome.cleared_attributes[0].attribute, attribute);
+            assert!(!path.exists());
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs CAP_LINUX_IMMUTABLE and a filesystem with inode flags"]
+    async fn test_refused_file_keeps_its_attributes() {
+        use filesystem::flags::{self, FS_APPEND_FL, FS_IMMUTABLE_FL};
+
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("logs");
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("linked");
+        std::fs::write(&path, b"secret").unwrap();
+        std::fs::hard_link(&path, temp_dir.path().join("other")).unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let dir_handle = std::fs::File::open(&dir).unwrap();
+        let current = flags::get_flags(&file).unwrap();
+        flags::set_flags(&file, current | FS_IMMUTABLE_FL).unwrap();
+        let dir_current = flags::get_flags(&dir_handle).unwrap();
+        flags::set_flags(&dir_handle, dir_current | FS_APPEND_FL).unwrap();
+
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            clear_immutable: true,
+            ..AmaterasuConfig::default()
+        });
+        let error = amaterasu.wipe_file(&path).await.unwrap_err();
+        assert!(error.downcast_ref::<NotWipeable>().is_some());
+        assert_ne!(flags::get_flags(&file).unwrap() & FS_IMMUTABLE_FL, 0);
+        assert_ne!(flags::get_flags(&dir_handle).unwrap() & FS_APPEND_FL, 0);
+
+        // Wiped once allowed; the directory gets its attribute back
+        let amaterasu = Amaterasu::new(AmaterasuConfig {
+            progress: false,
+            clear_immutable: true,
+            hardlinks: HardlinkPolicy::WipeAll,
+            ..AmaterasuConfig::default()
+        });
+        let outcome = amaterasu.wipe_file(&path).await.unwrap();
+        assert_eq!(outcome.cleared_attributes.len(), 2);
+        assert!(!path.exists());
+        assert_ne!(flags::get_flags(&dir_handle).unwrap() & FS_APPEND_FL, 0);
+
+        flags::set_flags(&dir_handle, dir_current).unwrap();
     }
 }
@@ -1,9 +1,48 @@
-use amaterasu::{config, Amaterasu, AmaterasuConfig, WipeMode};
+use amaterasu::io::WipeReport;
+use amaterasu::{
+    config, Amaterasu, AmaterasuConfig, HardlinkPolicy, NotWipeable, SharedExtentPolicy,
+    SpecialFilePolicy, WipeMode,
+};
 use clap::{Arg, Command};
 use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Exit status when a target can't be wiped where it lives (read-only,
+/// network or FUSE mount, ...), as opposed to a failed wipe (1)
+const EXIT_NOT_WIPEABLE: u8 = 3;
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(error_status(&e))
+        }
+    }
+}
+
+fn error_status(error: &anyhow::Error) -> u8 {
+    if error.downcast_ref::<NotWipeable>().is_some() {
+        EXIT_NOT_WIPEABLE
+    } else {
+        1
+    }
+}
+
+/// Failures outrank refusals: either way something was left behind, but
+/// only a refusal is expected
+fn report_status(report: &WipeReport) -> u8 {
+    if report.has_failures() {
+        1
+    } else if report.refused.is_empty() {
+        0
+    } else {
+        EXIT_NOT_WIPEABLE
+    }
+}
+
+async fn run() -> anyhow::Result<ExitCode> {
     let matches = Command::new("amaterasu")
         .version("0.1.0")
         .about("A modern, fast file secure deletion tool for Linux")
@@ -49,6 +88,32 @@ async fn main() -> anyhow::Result<()> {
                 .help("Force deletion without prompts, ignore non-existent files")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("follow-symlinks")
+                .long("follow-symlinks")
+                .help("Wipe the files symlinks point to; by default only the links are removed")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("hardlinks")
+                .long("hardlinks")
+                .help("Files with several hard links: refuse them, or wipe them and remove every name among the targets")
+                .value_parser(["refuse", "wipe-all"])
+                .default_value("refuse"),
+        )
+        .arg(
+            Arg::new("special-files")
+                .long("special-files")
+                .help("FIFOs, sockets and device nodes: skip them, or unlink them")
+                .value_parser(["skip", "unlink"])
+                .default_value("skip"),
+        )
+        .arg(
+            Arg::new("clear-immutable")
+                .long("clear-immutable")
+                .help("Clear immutable and append-only attributes before wiping (needs CAP_LINUX_IMMUTABLE)")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("config")
                 .long("config")
@@ -68,6 +133,37 @@ async fn main() -> anyhow::Result<()> {
                 .value_parser(clap::value_parser!(usize))
                 .default_value("3"),
         )
+        .arg(
+            Arg::new("fixed-timestamp")
+                .long("fixed-timestamp")
+                .value_name("SECONDS")
+                .help("Set timestamps to this Unix time before deletion instead of random times")
+                .value_parser(clap::value_parser!(i64)),
+        )
+        .arg(
+            Arg::new("refuse-shared")
+                .long("refuse-shared")
+                .help("Refuse files that share extents with other files or snapshots")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("snapshot-report")
+                .long("snapshot-report")
+                .help("Report which snapshots must be deleted for the data to be gone")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("allow-remote")
+                .long("allow-remote")
+                .help("Wipe files on network and FUSE filesystems instead of refusing")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("encryption-report")
+                .long("encryption-report")
+                .help("Report the key identifier protecting fscrypt or eCryptfs files")
+                .action(clap::ArgAction::SetTrue),
+        )
         .get_matches();
 
     // Handle config creation request
@@ -78,7 +174,7 @@ async fn main() -> anyhow::Result<()> {
             "✅ Default configuration created at: {}",
             config_path.display()
         );
-        return Ok(());
+        return Ok(ExitCode::SUCCESS);
     }
 
     // Load configuration file after parsing CLI args
@@ -110,6 +206,26 @@ async fn main() -> anyhow::Result<()> {
         mode,
         wipe_metadata: !matches.get_flag("no-metadata-wipe"),
         metadata_passes: *matches.get_one::<usize>("metadata-passes").unwrap(),
+        device_policies: config_file.devices.clone(),
+        shared_extents: if matches.get_flag("refuse-shared") {
+            SharedExtentPolicy::Refuse
+        } else {
+            config_file.defaults.shared_extents
+        },
+        snapshot_report: matches.get_flag("snapshot-report"),
+        allow_remote: matches.get_flag("allow-remote"),
+        encryption_report: matches.get_flag("encryption-report"),
+        fixed_timestamp: matches.get_one::<i64>("fixed-timestamp").copied(),
+        follow_symlinks: matches.get_flag("follow-symlinks"),
+        hardlinks: match matches.get_one::<String>("hardlinks").unwrap().as_str() {
+            "wipe-all" => HardlinkPolicy::WipeAll,
+            _ => HardlinkPolicy::Refuse,
+        },
+        special_files: match matches.get_one::<String>("special-files").unwrap().as_str() {
+            "unlink" => SpecialFilePolicy::Unlink,
+            _ => SpecialFilePolicy::Skip,
+        },
+        clear_immutable: matches.get_flag("clear-immutable"),
     };
 
     println!("🔥 Amaterasu - Secure File Deletion");
@@ -118,10 +234,41 @@ async fn main() -> anyhow::Result<()> {
     let amaterasu = Amaterasu::new(config);
 
     // Collect all files to wipe (expand directories if recursive flag is set)
-    let files_to_wipe = amaterasu.collect_files(&input_paths, recursive).await?;
+    let mut targets = amaterasu.collect_targets(&input_paths, recursive).await?;
+    // Directories the wipe leaves empty go too
+    targets.remove_empty_parents = true;
+
+    println!("Files to wipe: {}", targets.files.len());
+    if !targets.unlink.is_empty() {
+        println!(
+            "Links and special files to remove: {}",
+            targets.unlink.len()
+        );
+    }
+    if !targets.directories.is_empty() {
+        println!("Directories to remove: {}", targets.directories.len());
+    }
+
+    let report = amaterasu.wipe_targets(&targets).await?;
+    Ok(ExitCode::from(report_status(&report)))
+}
 
-    println!("Files to wipe: {}", files_to_wipe.len());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
 
-    amaterasu.wipe_files(&files_to_wipe).await?;
-    Ok(())
+    #[test]
+    fn test_exit_status() {
+        let refusal = NotWipeable::new(Path::new("/data/secret"), "it is immutable");
+        assert_eq!(error_status(&refusal.into()), EXIT_NOT_WIPEABLE);
+        assert_eq!(error_status(&anyhow::anyhow!("disk on fire")), 1);
+
+        let mut report = WipeReport::default();
+        assert_eq!(report_status(&report), 0);
+        report.refused.push(PathBuf::from("/data/secret"));
+        assert_eq!(report_status(&report), EXIT_NOT_WIPEABLE);
+        report.failed.push(PathBuf::from("/data/other"));
+        assert_eq!(report_status(&report), 1);
+    }
 }
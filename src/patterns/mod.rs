@@ -8,7 +8,7 @@ pub trait PatternGenerator {
 }
 
 pub enum WipePattern {
-    Random(ChaCha20Rng),
+    Random(Box<ChaCha20Rng>),
     Fixed(u8),
     Zeros,
     Ones,
@@ -50,7 +50,7 @@ impl WipePattern {
 pub fn create_random_generator() -> WipePattern {
     use rand::SeedableRng;
     let rng = ChaCha20Rng::from_entropy();
-    WipePattern::Random(rng)
+    WipePattern::Random(Box::new(rng))
 }
 
 pub fn create_pattern_sequence(mode: &crate::WipeMode) -> Vec<WipePattern> {
@@ -80,7 +80,7 @@ pub fn create_storage_aware_pattern_sequence(
     use crate::storage::StorageType;
 
     match storage_type {
-        StorageType::SSD { .. } | StorageType::NVMe { .. } => {
+        StorageType::SSD { .. } | StorageType::NVMe { .. } | StorageType::Removable { .. } => {
             // For SSDs and NVMe, multiple passes are unnecessary due to wear leveling
             // Single random pass is sufficient and reduces wear
             match mode {
@@ -92,6 +92,11 @@ pub fn create_storage_aware_pattern_sequence(
                 ],
             }
         }
+        StorageType::Memory { .. } => {
+            // An overwrite of a tmpfs/ramfs page replaces it in RAM; there is
+            // no medium remanence for extra passes to defeat
+            vec![create_random_generator()]
+        }
         StorageType::HDD { .. } => {
             // For HDDs, multiple passes can be beneficial for security
             match mode {
@@ -118,3 +123,42 @@ pub fn create_storage_aware_pattern_sequence(
         }
     }
 }
+
+/// Build a sequence with an explicit number of passes (from a device
+/// policy), cycling through the mode's full pattern list.
+pub fn create_pattern_sequence_with_passes(
+    mode: &crate::WipeMode,
+    passes: usize,
+) -> Vec<WipePattern> {
+    create_pattern_sequence(mode)
+        .iter()
+        .cycle()
+        .take(passes.max(1))
+        .cloned()
+        .collect()
+}
+
+/// Replace every fixed-byte pass with random data. Compressing filesystems
+/// store a block of zeros or 0x55 as a few bytes in a new extent, so only
+/// high-entropy passes actually cover the original blocks.
+pub fn make_incompressible(patterns: Vec<WipePattern>) -> Vec<WipePattern> {
+    patterns
+        .into_iter()
+        .map(|pattern| match pattern {
+            WipePattern::Random(_) => pattern,
+            _ => create_random_generator(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_make_incompressible() {
+        let patterns = make_incompressible(create_pattern_sequence(&crate::WipeMode::Paranoid));
+        assert_eq!(patterns.len(), 7);
+        assert!(patterns.iter().all(|pattern| pattern.name() == "random"));
+    }
+}
@@ -1,12 +1,31 @@
-use crate::{Path, PathBuf, Result};
+use crate::filesystem::fat::NameRules;
+use crate::io::pinned::PinnedEntry;
+use crate::{Path, Result};
+use anyhow::Context;
+use nix::sys::stat::futimens;
+use nix::sys::time::TimeSpec;
 use rand::Rng;
-use std::time::UNIX_EPOCH;
-use tokio::fs;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Random names tried per rename before giving up on finding a free one
+const NAME_ATTEMPTS: usize = 64;
 
 pub struct MetadataWiper {
     pub rename_iterations: usize,
     pub timestamp_randomization: bool,
     pub clear_extended_attributes: bool,
+    /// Filesystem-specific naming that makes each rename reuse the original
+    /// directory entries
+    pub name_rules: Option<NameRules>,
+    /// Seconds since the epoch to use for every timestamp instead of
+    /// random values
+    pub fixed_timestamp: Option<i64>,
+    /// Overwrite `user.*`/`trusted.*` values before removing them; useless
+    /// where a rewrite lands somewhere new (CoW) or nothing is on disk
+    pub overwrite_xattr_values: bool,
 }
 
 impl Default for MetadataWiper {
@@ -15,6 +34,9 @@ impl Default for MetadataWiper {
             rename_iterations: 3,
             timestamp_randomization: true,
             clear_extended_attributes: true,
+            name_rules: None,
+            fixed_timestamp: None,
+            overwrite_xattr_values: true,
         }
     }
 }
@@ -25,102 +47,163 @@ impl MetadataWiper {
             rename_iterations,
             timestamp_randomization: true,
             clear_extended_attributes: true,
+            name_rules: None,
+            fixed_timestamp: None,
+            overwrite_xattr_values: true,
         }
     }
 
+    pub fn with_name_rules(mut self, name_rules: Option<NameRules>) -> Self {
+        self.name_rules = name_rules;
+        self
+    }
+
+    pub fn with_fixed_timestamp(mut self, fixed_timestamp: Option<i64>) -> Self {
+        self.fixed_timestamp = fixed_timestamp;
+        self
+    }
+
+    pub fn with_xattr_overwrite(mut self, overwrite_xattr_values: bool) -> Self {
+        self.overwrite_xattr_values = overwrite_xattr_values;
+        self
+    }
+
     /// Wipe metadata for a file/directory before deletion
     pub async fn wipe_metadata(&self, path: &Path) -> Result<()> {
-        let mut current_path = path.to_path_buf();
+        let entry = if std::fs::symlink_metadata(path)?.is_dir() {
+            PinnedEntry::open_dir(path)?
+        } else {
+            PinnedEntry::open_file(path)?
+        };
+        self.wipe_pinned(entry).await
+    }
 
-        // 1. Randomize timestamps if enabled
-        if self.timestamp_randomization {
-            if let Err(e) = self.randomize_timestamps(&current_path).await {
+    /// Wipe metadata through already-open descriptors, so every step acts on
+    /// the inode that was wiped even if its path is swapped meanwhile
+    pub async fn wipe_pinned(&self, mut entry: PinnedEntry) -> Result<()> {
+        // 1. Drop the file's size and extents from the inode, durably,
+        // before its name changes: a crash from here on leaves either an
+        // empty file under the original name or a random name
+        if !entry.is_dir() {
+            truncate_and_sync(entry.file())
+                .with_context(|| format!("Failed to truncate {}", entry.path().display()))?;
+        }
+
+        // 2. Clear extended attributes if enabled (Linux-specific)
+        if self.clear_extended_attributes {
+            if let Err(e) = self.clear_extended_attributes(&entry).await {
                 eprintln!(
-                    "Warning: Failed to randomize timestamps for {}: {}",
-                    current_path.display(),
+                    "Warning: Failed to clear extended attributes for {}: {}",
+                    entry.path().display(),
                     e
                 );
             }
         }
 
-        // 2. Clear extended attributes if enabled (Linux-specific)
-        if self.clear_extended_attributes {
-            if let Err(e) = self.clear_extended_attributes(&current_path).await {
+        // 3. Randomize timestamps if enabled; after truncating, which
+        // sets mtime to now
+        if self.timestamp_randomization {
+            if let Err(e) = self.randomize_timestamps(entry.file()).await {
                 eprintln!(
-                    "Warning: Failed to clear extended attributes for {}: {}",
-                    current_path.display(),
+                    "Warning: Failed to randomize timestamps for {}: {}",
+                    entry.path().display(),
                     e
                 );
             }
         }
 
-        // 3. Progressive filename shortening and randomization
+        // 4. Rename to random names of the original length so each new
+        // entry can take the old one's place, fsyncing the directory so
+        // every rename reaches the disk rather than only the last
         for iteration in 0..self.rename_iterations {
-            let new_path = self.generate_random_name(&current_path, iteration)?;
-
-            if let Err(e) = fs::rename(&current_path, &new_path).await {
+            if let Err(e) = self.rename_to_random_name(&mut entry, iteration) {
+                // A swapped name means someone else owns it now; stop here
+                entry.verify()?;
                 eprintln!(
-                    "Warning: Failed to rename {} to {} (iteration {}): {}",
-                    current_path.display(),
-                    new_path.display(),
+                    "Warning: Failed to rename {} (iteration {}): {}",
+                    entry.path().display(),
                     iteration + 1,
                     e
                 );
                 break;
             }
 
-            current_path = new_path;
+            if let Err(e) = entry.sync_dir() {
+                eprintln!("Warning: {}", e);
+            }
         }
 
-        // 4. Final unlink/removal
-        self.secure_unlink(&current_path).await?;
+        // 5. Final unlink/removal, only if the name still holds our inode
+        entry.remove()?;
+        entry.sync_dir()?;
 
         Ok(())
     }
 
-    /// Randomize file/directory timestamps
-    async fn randomize_timestamps(&self, path: &Path) -> Result<()> {
-        use std::time::Duration;
-
-        let mut rng = rand::thread_rng();
-
-        // Generate random timestamp within the last 10 years
-        let random_secs = rng.gen_range(0..315_360_000); // ~10 years in seconds
-        let _random_time = UNIX_EPOCH + Duration::from_secs(random_secs);
-
-        // Set both access and modification times to the same random value
-        let file = fs::File::open(path).await?;
-
-        // Use filetime crate functionality through std library where possible
-        // Note: This is a simplified implementation - full implementation would use filetime crate
-        drop(file); // Close file handle
+    /// Overwrite atime and mtime with independent random times from the
+    /// last ten years, or with `fixed_timestamp` when set. The kernel always
+    /// sets ctime to now; it can't be chosen.
+    pub async fn randomize_timestamps(&self, file: &File) -> Result<()> {
+        let (atime, mtime) = match self.fixed_timestamp {
+            Some(secs) => {
+                let fixed = TimeSpec::new(secs, 0);
+                (fixed, fixed)
+            }
+            None => (random_timestamp(), random_timestamp()),
+        };
 
+        futimens(file.as_raw_fd(), &atime, &mtime).context("futimens failed")?;
         Ok(())
     }
 
-    /// Clear extended attributes (Linux xattrs)
-    async fn clear_extended_attributes(&self, _path: &Path) -> Result<()> {
-        // Note: This would require the xattr crate for full implementation
-        // For now, this is a placeholder that doesn't fail
-        // In a full implementation, we would:
-        // 1. List all extended attributes
-        // 2. Remove each one individually
-        // 3. Handle errors appropriately
-
+    /// Remove extended attributes, ACLs and security labels, reporting
+    /// each one that has to stay
+    async fn clear_extended_attributes(&self, entry: &PinnedEntry) -> Result<()> {
+        let failures = super::xattr::remove_all(entry.file(), self.overwrite_xattr_values)?;
+        for failure in failures {
+            eprintln!(
+                "Warning: Could not remove extended attribute {} from {}: {}",
+                failure.name,
+                entry.path().display(),
+                failure.reason
+            );
+        }
         Ok(())
     }
 
-    /// Generate a random filename for progressive renaming
-    fn generate_random_name(&self, current_path: &Path, iteration: usize) -> Result<PathBuf> {
-        let parent = current_path.parent().unwrap_or_else(|| Path::new("/"));
+    /// Rename the entry to a fresh random name in the same directory,
+    /// retrying when the name is taken so no other file is replaced
+    fn rename_to_random_name(&self, entry: &mut PinnedEntry, iteration: usize) -> Result<()> {
+        for _ in 0..NAME_ATTEMPTS {
+            let new_name = self.random_name(entry.name(), iteration);
+            if entry.rename_noreplace(&new_name)? {
+                return Ok(());
+            }
+        }
 
+        Err(anyhow::anyhow!(
+            "no unused name found after {} attempts",
+            NAME_ATTEMPTS
+        ))
+    }
+
+    /// Generate a random filename for renaming. Every rename but the last
+    /// keeps the original length (or the filesystem's `name_rules`); the
+    /// last of several shrinks to one character, leaving as little as
+    /// possible in the entry that is finally removed.
+    fn random_name(&self, current_name: &OsStr, iteration: usize) -> OsString {
         let mut rng = rand::thread_rng();
+        if let Some(rules) = self.name_rules {
+            let name = rules.same_length_name(&current_name.to_string_lossy(), &mut rng);
+            return name.into();
+        }
 
-        // Create progressively shorter random names
-        let name_length = match iteration {
-            0 => 16, // First pass: long random name
-            1 => 8,  // Second pass: shorter
-            _ => 1,  // Final pass: single character
+        let last = iteration + 1 >= self.rename_iterations && self.rename_iterations > 1;
+        let name_length = if last {
+            1
+        } else {
+            // Byte length, which is what the directory entry stores
+            current_name.len().max(1)
         };
 
         let random_name: String = (0..name_length)
@@ -130,20 +213,7 @@ impl MetadataWiper {
             })
             .collect();
 
-        Ok(parent.join(random_name))
-    }
-
-    /// Securely unlink/remove the file or directory
-    async fn secure_unlink(&self, path: &Path) -> Result<()> {
-        let metadata = fs::metadata(path).await?;
-
-        if metadata.is_dir() {
-            fs::remove_dir(path).await?;
-        } else {
-            fs::remove_file(path).await?;
-        }
-
-        Ok(())
+        random_name.into()
     }
 
     /// Wipe metadata for a file after the content has been wiped
@@ -159,6 +229,26 @@ impl MetadataWiper {
     }
 }
 
+/// Truncate a wiped file to zero and fsync it, so the inode no longer
+/// records its size or extents once it is unlinked
+pub fn truncate_and_sync(file: &File) -> Result<()> {
+    file.set_len(0)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// A random moment within the last ten years, down to the nanosecond so
+/// the value doesn't stand out on filesystems storing sub-second times
+fn random_timestamp() -> TimeSpec {
+    let mut rng = rand::thread_rng();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0);
+    let secs = now - rng.gen_range(0..315_360_000); // ~10 years in seconds
+    TimeSpec::new(secs, rng.gen_range(0..1_000_000_000))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -176,23 +266,113 @@ mod tests {
 
     #[tokio::test]
     async fn test_random_name_generation() {
-        let temp_dir = TempDir::new().unwrap();
-        let test_path = temp_dir.path().join("test.txt");
+        let test_name = OsStr::new("quarterly-report.txt");
 
         let wiper = MetadataWiper::default();
 
-        let random_name_0 = wiper.generate_random_name(&test_path, 0).unwrap();
-        let random_name_1 = wiper.generate_random_name(&test_path, 1).unwrap();
-        let random_name_2 = wiper.generate_random_name(&test_path, 2).unwrap();
+        let random_name_0 = wiper.random_name(test_name, 0);
+        let random_name_1 = wiper.random_name(test_name, 1);
+        let random_name_2 = wiper.random_name(test_name, 2);
 
-        // Check that names have expected lengths
-        assert_eq!(random_name_0.file_name().unwrap().len(), 16);
-        assert_eq!(random_name_1.file_name().unwrap().len(), 8);
-        assert_eq!(random_name_2.file_name().unwrap().len(), 1);
+        // Names keep the original length until the final rename
+        assert_eq!(random_name_0.len(), 20);
+        assert_eq!(random_name_1.len(), 20);
+        assert_eq!(random_name_2.len(), 1);
 
         // Names should be different
         assert_ne!(random_name_0, random_name_1);
         assert_ne!(random_name_1, random_name_2);
+
+        // A single rename keeps the length too
+        let single = MetadataWiper::new(1);
+        assert_eq!(single.random_name(test_name, 0).len(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_rename_never_replaces_other_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("a");
+        std::fs::write(&test_file, b"wiped").unwrap();
+
+        // Take every one-character name but the file's own
+        let chars = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        for c in chars.chars().filter(|&c| c != 'a') {
+            std::fs::write(temp_dir.path().join(c.to_string()), b"keep").unwrap();
+        }
+
+        let wiper = MetadataWiper::new(1);
+        let mut entry = PinnedEntry::open_file(&test_file).unwrap();
+        assert!(wiper.rename_to_random_name(&mut entry, 0).is_err());
+        for c in chars.chars().filter(|&c| c != 'a') {
+            let other = std::fs::read(temp_dir.path().join(c.to_string())).unwrap();
+            assert_eq!(other, b"keep");
+        }
+        assert_eq!(std::fs::read(&test_file).unwrap(), b"wiped");
+    }
+
+    #[tokio::test]
+    async fn test_timestamps_are_set() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("test.txt");
+        std::fs::write(&test_file, b"test content").unwrap();
+
+        let fixed = MetadataWiper::default().with_fixed_timestamp(Some(1_000_000_000));
+        let file = std::fs::File::open(&test_file).unwrap();
+        let dir = std::fs::File::open(temp_dir.path()).unwrap();
+        fixed.randomize_timestamps(&file).await.unwrap();
+        fixed.randomize_timestamps(&dir).await.unwrap();
+        for path in [test_file.as_path(), temp_dir.path()] {
+            let metadata = std::fs::metadata(path).unwrap();
+            assert_eq!(
+                (metadata.atime(), metadata.atime_nsec()),
+                (1_000_000_000, 0)
+            );
+            assert_eq!(
+                (metadata.mtime(), metadata.mtime_nsec()),
+                (1_000_000_000, 0)
+            );
+        }
+
+        let random = MetadataWiper::default();
+        random.randomize_timestamps(&file).await.unwrap();
+        let metadata = std::fs::metadata(&test_file).unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        for secs in [metadata.atime(), metadata.mtime()] {
+            assert!(secs <= now && secs > now - 315_360_000);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_same_length_names() {
+        let wiper = MetadataWiper::default().with_name_rules(Some(NameRules::Exfat));
+
+        for iteration in 0..3 {
+            let name = wiper.random_name(OsStr::new("Holiday Photos.zip"), iteration);
+            assert_eq!(name.len(), 18);
+        }
+    }
+
+    #[test]
+    fn test_truncate_and_sync() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("test.bin");
+        std::fs::write(&test_file, vec![0xAA; 64 * 1024]).unwrap();
+
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&test_file)
+            .unwrap();
+        truncate_and_sync(&file).unwrap();
+        let metadata = std::fs::metadata(&test_file).unwrap();
+        assert_eq!(metadata.len(), 0);
+        assert_eq!(metadata.blocks(), 0);
     }
 
     #[tokio::test]
@@ -1,5 +1,6 @@
 pub mod metadata;
 pub mod verification;
+pub mod xattr;
 
 use crate::Result;
 use std::path::Path;
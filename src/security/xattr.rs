@@ -0,0 +1,167 @@
+use crate::Result;
+use anyhow::Context;
+use rand::RngCore;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// An extended attribute left on the file, and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XattrFailure {
+    pub name: String,
+    pub reason: String,
+}
+
+/// Remove every extended attribute of an open file or directory:
+/// `user.*`, `trusted.*`, POSIX ACLs (`system.posix_acl_*`) and
+/// `security.*` labels, as far as the caller is allowed to. Free-form
+/// values (`user.*`, `trusted.*`) are first overwritten with random bytes
+/// of the same length when `overwrite` is set, so a value kept in its own
+/// block doesn't survive in it. Returns the attributes that remain.
+pub fn remove_all(file: &File, overwrite: bool) -> Result<Vec<XattrFailure>> {
+    let fd = file.as_raw_fd();
+    let names = list(fd).context("Failed to list extended attributes")?;
+
+    let mut failures = Vec::new();
+    for name in names {
+        let display = name.to_string_lossy().into_owned();
+        if overwrite && is_free_form(&display) {
+            // Best effort; removal below is what matters
+            let _ = overwrite_value(fd, &name);
+        }
+
+        let result = unsafe { libc::fremovexattr(fd, name.as_ptr()) };
+        if result != 0 {
+            let error = io::Error::last_os_error();
+            // Already gone, e.g. a default ACL dropped along with another
+            if error.raw_os_error() != Some(libc::ENODATA) {
+                failures.push(XattrFailure {
+                    reason: describe_error(&display, &error),
+                    name: display,
+                });
+            }
+        }
+    }
+
+    Ok(failures)
+}
+
+fn is_free_form(name: &str) -> bool {
+    name.starts_with("user.") || name.starts_with("trusted.")
+}
+
+fn list(fd: RawFd) -> io::Result<Vec<CString>> {
+    loop {
+        let size = unsafe { libc::flistxattr(fd, std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buffer = vec![0u8; size as usize];
+        let size = unsafe { libc::flistxattr(fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+        if size < 0 {
+            let error = io::Error::last_os_error();
+            // The list grew between the two calls
+            if error.raw_os_error() == Some(libc::ERANGE) {
+                continue;
+            }
+            return Err(error);
+        }
+
+        buffer.truncate(size as usize);
+        return Ok(buffer
+            .split(|&b| b == 0)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| CString::new(name).ok())
+            .collect());
+    }
+}
+
+fn overwrite_value(fd: RawFd, name: &CStr) -> io::Result<()> {
+    let size = unsafe { libc::fgetxattr(fd, name.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut junk = vec![0u8; size as usize];
+    rand::thread_rng().fill_bytes(&mut junk);
+    let result = unsafe {
+        libc::fsetxattr(
+            fd,
+            name.as_ptr(),
+            junk.as_ptr().cast(),
+            junk.len(),
+            libc::XATTR_REPLACE,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn describe_error(name: &str, error: &io::Error) -> String {
+    match error.raw_os_error() {
+        Some(libc::EPERM) | Some(libc::EACCES) if name.starts_with("trusted.") => {
+            "trusted attributes need CAP_SYS_ADMIN".to_string()
+        }
+        Some(libc::EPERM) | Some(libc::EACCES) if name.starts_with("security.") => {
+            "refused by the security module".to_string()
+        }
+        Some(libc::EPERM) | Some(libc::EACCES) => "permission denied".to_string(),
+        Some(libc::EOPNOTSUPP) => "not supported by the filesystem".to_string(),
+        _ => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use tempfile::NamedTempFile;
+
+    fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let c_name = CString::new(name).unwrap();
+        let result = unsafe {
+            libc::lsetxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_user_attributes() {
+        let file = NamedTempFile::new().unwrap();
+        // Skip where the temp filesystem has no user xattrs
+        if set(file.path(), "user.comment", b"secret note").is_err() {
+            return;
+        }
+        set(file.path(), "user.origin", &[0x42; 2048]).unwrap();
+
+        let failures = remove_all(file.as_file(), true).unwrap();
+        assert!(failures.is_empty(), "{:?}", failures);
+        assert!(list(file.as_file().as_raw_fd()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_describe_error() {
+        let denied = io::Error::from_raw_os_error(libc::EPERM);
+        assert!(describe_error("trusted.md5", &denied).contains("CAP_SYS_ADMIN"));
+        assert!(describe_error("security.selinux", &denied).contains("security module"));
+        assert_eq!(describe_error("user.a", &denied), "permission denied");
+    }
+}
@@ -1,76 +1,556 @@
-use super::StorageType;
+use super::{StorageType, Transport};
+use crate::sysroot::{read_string, read_value, SysRoot};
 use anyhow::{Context, Result};
+use nix::sys::stat::{major, makedev, minor};
+use nix::sys::statfs::statfs;
 use std::fs;
 use std::os::unix::fs::MetadataExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// Stacked devices deeper than this are treated as unknown rather than
+/// followed further (guards against loop devices backed by themselves).
+const MAX_STACK_DEPTH: usize = 8;
+
+/// The block device backing a path, with the identifiers used to match
+/// per-device policy overrides.
+#[derive(Debug, Clone)]
+pub struct BlockDevice {
+    /// Whole-disk kernel name, e.g. `sdb`, `nvme0n1` or `dm-0`
+    pub name: String,
+    /// Partition kernel name when the path lives on a partition, e.g. `sdb1`
+    pub partition: Option<String>,
+    pub kind: DeviceKind,
+    pub serial: Option<String>,
+    pub wwn: Option<String>,
+    pub removable: bool,
+    pub transport: Transport,
+    pub storage_type: StorageType,
+    /// Devices this one is built on (dm/md members, a loop device's backing
+    /// file's device). Empty for physical disks.
+    pub underlying: Vec<BlockDevice>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceKind {
+    Disk,
+    DeviceMapper {
+        name: Option<String>,
+        encrypted: bool,
+    },
+    Raid {
+        level: Option<String>,
+    },
+    Loop {
+        backing_file: Option<PathBuf>,
+    },
+}
+
+impl BlockDevice {
+    /// This device followed by everything it is stacked on, depth first
+    pub fn layers(&self) -> Vec<&BlockDevice> {
+        let mut layers = vec![self];
+        for device in &self.underlying {
+            layers.extend(device.layers());
+        }
+        layers
+    }
+
+    /// Whether any layer is a dm-crypt mapping
+    pub fn is_encrypted(&self) -> bool {
+        self.layers().iter().any(|device| {
+            matches!(
+                device.kind,
+                DeviceKind::DeviceMapper {
+                    encrypted: true,
+                    ..
+                }
+            )
+        })
+    }
+}
 
 pub fn detect_storage_type(path: &Path) -> Result<StorageType> {
-    let device = get_device_for_path(path)?;
-    detect_storage_for_device(&device)
+    Ok(match detect_block_device(path)? {
+        Some(device) => device.storage_type,
+        None => detect_memory_storage(path).unwrap_or(StorageType::Unknown),
+    })
+}
+
+/// tmpfs and ramfs have no block device; recognise them by `statfs` magic
+pub fn detect_memory_storage(path: &Path) -> Option<StorageType> {
+    const TMPFS_MAGIC: i64 = 0x0102_1994;
+    const RAMFS_MAGIC: i64 = 0x8584_58F6;
+
+    match statfs(path).ok()?.filesystem_type().0 as i64 {
+        TMPFS_MAGIC => Some(StorageType::Memory { swappable: true }),
+        RAMFS_MAGIC => Some(StorageType::Memory { swappable: false }),
+        _ => None,
+    }
 }
 
-fn get_device_for_path(path: &Path) -> Result<String> {
+/// Describe a block device by kernel name (`sda2`, `dm-0`), looking it up
+/// among the `/sys/dev/block` links
+pub fn detect_block_device_by_name(root: &SysRoot, name: &str) -> Option<BlockDevice> {
+    fs::read_dir(root.sys("dev/block"))
+        .ok()?
+        .flatten()
+        .find(|entry| {
+            fs::canonicalize(entry.path())
+                .ok()
+                .and_then(|path| file_name(&path))
+                .is_some_and(|found| found == name)
+        })
+        .and_then(|entry| {
+            let devnum = entry.file_name().to_string_lossy().into_owned();
+            let (major, minor) = devnum.split_once(':')?;
+            describe_devnum(root, makedev(major.parse().ok()?, minor.parse().ok()?), 0)
+        })
+}
+
+pub fn detect_block_device(path: &Path) -> Result<Option<BlockDevice>> {
     let metadata = fs::metadata(path).context("Failed to get file metadata")?;
-    let dev = metadata.dev();
+    Ok(detect_block_device_by_devnum(
+        &SysRoot::host(),
+        metadata.dev(),
+    ))
+}
+
+/// Describe the block device with the given `st_dev`, resolving it through
+/// `/sys/dev/block` so every major number is covered.
+pub fn detect_block_device_by_devnum(root: &SysRoot, dev: u64) -> Option<BlockDevice> {
+    describe_devnum(root, dev, 0)
+}
+
+pub fn detect_storage_for_device(root: &SysRoot, device: &str) -> Result<StorageType> {
+    Ok(describe_device(root, device, None, 0).storage_type)
+}
+
+fn describe_devnum(root: &SysRoot, dev: u64, depth: usize) -> Option<BlockDevice> {
+    // Anonymous devices (tmpfs, overlay, network mounts) have no sysfs entry
+    let sys_dev = root.sys(format!("dev/block/{}:{}", major(dev), minor(dev)));
+    let (name, partition) = resolve_sysfs_device(&sys_dev)?;
+    Some(describe_device(root, &name, partition, depth))
+}
+
+/// Turn a sysfs device link into `(disk, partition)` kernel names
+fn resolve_sysfs_device(link: &Path) -> Option<(String, Option<String>)> {
+    let sys_path = fs::canonicalize(link).ok()?;
+    let name = file_name(&sys_path)?;
+
+    if sys_path.join("partition").exists() {
+        let disk = sys_path.parent().and_then(file_name)?;
+        Some((disk, Some(name)))
+    } else {
+        Some((name, None))
+    }
+}
 
-    let major = (dev >> 8) & 0xff;
-    let minor = dev & 0xff;
+fn describe_device(
+    root: &SysRoot,
+    name: &str,
+    partition: Option<String>,
+    depth: usize,
+) -> BlockDevice {
+    let sys_block = root.sys("block").join(name);
+    let kind = detect_kind(&sys_block, name);
 
-    let device_name = if major == 8 {
-        format!("sd{}", char::from(b'a' + (minor / 16) as u8))
-    } else if major == 259 {
-        format!("nvme{}n{}", minor / 2, (minor % 2) + 1)
+    let underlying = if depth >= MAX_STACK_DEPTH {
+        Vec::new()
     } else {
-        return Ok("unknown".to_string());
+        match &kind {
+            DeviceKind::Disk => Vec::new(),
+            DeviceKind::DeviceMapper { .. } | DeviceKind::Raid { .. } => {
+                describe_slaves(root, &sys_block, depth)
+            }
+            DeviceKind::Loop { backing_file } => backing_file
+                .as_ref()
+                .and_then(|file| fs::metadata(root.path(file)).ok())
+                .and_then(|metadata| describe_devnum(root, metadata.dev(), depth + 1))
+                .into_iter()
+                .collect(),
+        }
+    };
+
+    let removable = read_value(sys_block.join("removable")) == Some(1);
+    let transport = detect_transport(root, name);
+    let storage_type = match kind {
+        DeviceKind::Disk => classify_device(root, name, removable, transport),
+        _ => combine_storage_types(underlying.iter().map(|device| &device.storage_type)),
+    };
+
+    BlockDevice {
+        name: name.to_string(),
+        partition,
+        serial: read_device_serial(root, name),
+        wwn: read_device_wwn(root, name),
+        kind,
+        removable,
+        transport,
+        storage_type,
+        underlying,
+    }
+}
+
+fn detect_kind(sys_block: &Path, name: &str) -> DeviceKind {
+    if sys_block.join("dm").exists() {
+        DeviceKind::DeviceMapper {
+            name: read_string(sys_block.join("dm/name")),
+            encrypted: read_string(sys_block.join("dm/uuid"))
+                .is_some_and(|uuid| uuid.starts_with("CRYPT-")),
+        }
+    } else if sys_block.join("md").exists() {
+        DeviceKind::Raid {
+            level: read_string(sys_block.join("md/level")),
+        }
+    } else if sys_block.join("loop").exists() || name.starts_with("loop") {
+        DeviceKind::Loop {
+            backing_file: read_string(sys_block.join("loop/backing_file"))
+                .map(|file| PathBuf::from(file.strip_suffix(" (deleted)").unwrap_or(&file))),
+        }
+    } else {
+        DeviceKind::Disk
+    }
+}
+
+fn describe_slaves(root: &SysRoot, sys_block: &Path, depth: usize) -> Vec<BlockDevice> {
+    let Ok(entries) = fs::read_dir(sys_block.join("slaves")) else {
+        return Vec::new();
     };
 
-    Ok(device_name)
+    let mut slaves: Vec<_> = entries
+        .flatten()
+        .filter_map(|entry| resolve_sysfs_device(&entry.path()))
+        .collect();
+    slaves.sort();
+
+    slaves
+        .into_iter()
+        .map(|(disk, partition)| describe_device(root, &disk, partition, depth + 1))
+        .collect()
 }
 
-fn detect_storage_for_device(device: &str) -> Result<StorageType> {
-    if device == "unknown" {
-        return Ok(StorageType::Unknown);
+/// Storage policy for a device built from several others: any magnetic
+/// member means the data may sit on a platter, so the most conservative
+/// member decides.
+fn combine_storage_types<'a>(types: impl Iterator<Item = &'a StorageType>) -> StorageType {
+    let types: Vec<_> = types.collect();
+
+    if types.is_empty() || types.iter().any(|t| matches!(t, StorageType::Unknown)) {
+        return StorageType::Unknown;
+    }
+
+    if let Some(block_size) = types
+        .iter()
+        .filter_map(|t| match t {
+            StorageType::HDD { block_size, .. } => Some(*block_size),
+            _ => None,
+        })
+        .max()
+    {
+        return StorageType::HDD {
+            rotational: true,
+            block_size,
+        };
+    }
+
+    if let Some(removable) = types.iter().find(|t| t.is_removable()) {
+        return (*removable).clone();
+    }
+
+    if types.iter().all(|t| matches!(t, StorageType::NVMe { .. })) {
+        let optimal_io_size = types
+            .iter()
+            .map(|t| t.get_optimal_block_size())
+            .max()
+            .unwrap_or(4096);
+        return StorageType::NVMe { optimal_io_size };
+    }
+
+    let trim_support = types.iter().all(|t| match t {
+        StorageType::SSD { trim_support } => *trim_support,
+        _ => true,
+    });
+    StorageType::SSD { trim_support }
+}
+
+fn classify_device(
+    root: &SysRoot,
+    device: &str,
+    removable: bool,
+    transport: Transport,
+) -> StorageType {
+    let sys_block = root.sys("block").join(device);
+
+    // Flash media behind USB bridges and card readers routinely report
+    // rotational=1, so the transport wins over the rotational flag.
+    if removable || matches!(transport, Transport::Usb | Transport::Mmc) {
+        return StorageType::Removable {
+            transport,
+            trim_support: check_trim_support(&sys_block),
+        };
     }
 
     if device.starts_with("nvme") {
-        let optimal_io_size =
-            read_sys_value(&format!("/sys/block/{}/queue/optimal_io_size", device)).unwrap_or(4096);
-        return Ok(StorageType::NVMe { optimal_io_size });
+        let optimal_io_size = read_value(sys_block.join("queue/optimal_io_size"))
+            .filter(|size| *size > 0)
+            .unwrap_or(4096);
+        return StorageType::NVMe { optimal_io_size };
     }
 
-    let rotational_path = format!("/sys/block/{}/queue/rotational", device);
-    let rotational = read_sys_value(&rotational_path).unwrap_or(1) == 1;
+    let rotational = read_value(sys_block.join("queue/rotational")).unwrap_or(1) == 1;
 
     if rotational {
-        let block_size = read_sys_value(&format!("/sys/block/{}/queue/logical_block_size", device))
-            .unwrap_or(512);
-        Ok(StorageType::HDD {
+        let block_size = read_value(sys_block.join("queue/logical_block_size")).unwrap_or(512);
+        StorageType::HDD {
             rotational: true,
             block_size,
-        })
+        }
+    } else {
+        let trim_support = check_trim_support(&sys_block);
+        StorageType::SSD { trim_support }
+    }
+}
+
+fn detect_transport(root: &SysRoot, device: &str) -> Transport {
+    if device.starts_with("nvme") {
+        return Transport::Nvme;
+    }
+    if device.starts_with("mmcblk") {
+        return Transport::Mmc;
+    }
+
+    match fs::canonicalize(root.sys("block").join(device)) {
+        Ok(sys_path) => transport_from_sysfs_path(&sys_path),
+        Err(_) => Transport::Unknown,
+    }
+}
+
+/// Classify the bus a disk hangs off from its canonical `/sys/devices` path.
+/// USB is checked first because USB card readers also contain an mmc/scsi
+/// segment further down.
+fn transport_from_sysfs_path(sys_path: &Path) -> Transport {
+    let sys_path = sys_path.to_string_lossy();
+
+    if sys_path.contains("/usb") {
+        Transport::Usb
+    } else if sys_path.contains("/mmc") {
+        Transport::Mmc
+    } else if sys_path.contains("/nvme") {
+        Transport::Nvme
+    } else if sys_path.contains("/ata") {
+        Transport::Sata
+    } else if sys_path.contains("/virtio") {
+        Transport::Virtio
     } else {
-        let trim_support = check_trim_support(device);
-        Ok(StorageType::SSD { trim_support })
+        Transport::Unknown
+    }
+}
+
+fn read_device_serial(root: &SysRoot, device: &str) -> Option<String> {
+    let device_dir = root.sys("block").join(device).join("device");
+    if let Some(serial) = read_string(device_dir.join("serial")) {
+        return Some(serial);
     }
+
+    // USB mass storage only exposes the serial on the USB device a few
+    // levels above the SCSI device
+    let devices_dir = root.sys("devices");
+    let mut dir = fs::canonicalize(&device_dir).ok()?;
+    while dir.pop() && dir.starts_with(&devices_dir) && dir != devices_dir {
+        if let Some(serial) = read_string(dir.join("serial")) {
+            return Some(serial);
+        }
+    }
+
+    None
+}
+
+fn read_device_wwn(root: &SysRoot, device: &str) -> Option<String> {
+    let sys_block = root.sys("block").join(device);
+    read_string(sys_block.join("wwid")).or_else(|| read_string(sys_block.join("device/wwid")))
+}
+
+/// Normalise a WWN/WWID for comparison: case-insensitive and without the
+/// `naa.`/`eui.`/`0x` prefixes different tools print.
+pub fn normalize_wwn(wwn: &str) -> String {
+    let wwn = wwn.trim().to_lowercase();
+    let wwn = wwn
+        .strip_prefix("naa.")
+        .or_else(|| wwn.strip_prefix("eui."))
+        .or_else(|| wwn.strip_prefix("0x"))
+        .unwrap_or(&wwn);
+    wwn.to_string()
 }
 
-fn read_sys_value(path: &str) -> Option<usize> {
-    fs::read_to_string(path).ok()?.trim().parse().ok()
+fn file_name(path: &Path) -> Option<String> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
 }
 
-fn check_trim_support(device: &str) -> bool {
-    let discard_path = format!("/sys/block/{}/queue/discard_granularity", device);
-    read_sys_value(&discard_path).unwrap_or(0) > 0
+fn check_trim_support(sys_block: &Path) -> bool {
+    read_value(sys_block.join("queue/discard_granularity")).unwrap_or(0) > 0
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn fixture(name: &str) -> SysRoot {
+        SysRoot::new(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(name),
+        )
+    }
+
     #[test]
     fn test_device_detection() {
-        if let Ok(storage_type) = detect_storage_for_device("sda") {
+        if let Ok(storage_type) = detect_storage_for_device(&SysRoot::host(), "sda") {
             println!("Storage type: {:?}", storage_type);
         }
     }
+
+    #[test]
+    fn test_transport_from_sysfs_path() {
+        let usb = Path::new(
+            "/sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb",
+        );
+        let sata = Path::new(
+            "/sys/devices/pci0000:00/0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0/block/sda",
+        );
+        let mmc = Path::new("/sys/devices/platform/soc/mmc_host/mmc0/mmc0:aaaa/block/mmcblk0");
+
+        assert_eq!(transport_from_sysfs_path(usb), Transport::Usb);
+        assert_eq!(transport_from_sysfs_path(sata), Transport::Sata);
+        assert_eq!(transport_from_sysfs_path(mmc), Transport::Mmc);
+    }
+
+    #[test]
+    fn test_normalize_wwn() {
+        assert_eq!(normalize_wwn("naa.5000C500A1B2C3D4"), "5000c500a1b2c3d4");
+        assert_eq!(normalize_wwn("0x5000c500a1b2c3d4\n"), "5000c500a1b2c3d4");
+        assert_eq!(normalize_wwn("eui.0025388b91b23e1d"), "0025388b91b23e1d");
+    }
+
+    #[test]
+    fn test_nvme_fixture() {
+        let device = detect_block_device_by_devnum(&fixture("nvme"), makedev(259, 2)).unwrap();
+
+        assert_eq!(device.name, "nvme0n1");
+        assert_eq!(device.partition.as_deref(), Some("nvme0n1p2"));
+        assert_eq!(device.kind, DeviceKind::Disk);
+        assert_eq!(device.transport, Transport::Nvme);
+        assert_eq!(device.serial.as_deref(), Some("S4EWNX0R123456"));
+        assert_eq!(device.wwn.as_deref(), Some("eui.0025388b91b23e1d"));
+        assert!(matches!(
+            device.storage_type,
+            StorageType::NVMe {
+                optimal_io_size: 4096
+            }
+        ));
+    }
+
+    #[test]
+    fn test_sata_ssd_fixture() {
+        let device = detect_block_device_by_devnum(&fixture("sata-ssd"), makedev(8, 2)).unwrap();
+
+        assert_eq!(device.name, "sda");
+        assert_eq!(device.partition.as_deref(), Some("sda2"));
+        assert_eq!(device.transport, Transport::Sata);
+        assert!(matches!(
+            device.storage_type,
+            StorageType::SSD { trim_support: true }
+        ));
+    }
+
+    #[test]
+    fn test_hdd_fixture() {
+        let device = detect_block_device_by_devnum(&fixture("hdd"), makedev(8, 17)).unwrap();
+
+        assert_eq!(device.name, "sdb");
+        assert_eq!(device.partition.as_deref(), Some("sdb1"));
+        assert!(matches!(
+            device.storage_type,
+            StorageType::HDD {
+                rotational: true,
+                block_size: 4096
+            }
+        ));
+    }
+
+    #[test]
+    fn test_usb_stick_fixture() {
+        let device = detect_block_device_by_devnum(&fixture("usb-stick"), makedev(8, 49)).unwrap();
+
+        // The stick claims to be rotational; the USB transport wins
+        assert!(device.removable);
+        assert_eq!(device.serial.as_deref(), Some("4C530001230918110463"));
+        assert!(matches!(
+            device.storage_type,
+            StorageType::Removable {
+                transport: Transport::Usb,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_dm_crypt_fixture() {
+        let device = detect_block_device_by_devnum(&fixture("dm-crypt"), makedev(254, 0)).unwrap();
+
+        assert_eq!(device.name, "dm-0");
+        assert_eq!(
+            device.kind,
+            DeviceKind::DeviceMapper {
+                name: Some("luks-3f2a".to_string()),
+                encrypted: true
+            }
+        );
+        assert!(device.is_encrypted());
+        assert_eq!(device.underlying.len(), 1);
+        assert_eq!(device.underlying[0].name, "sda");
+        assert_eq!(device.underlying[0].partition.as_deref(), Some("sda2"));
+        assert!(matches!(device.storage_type, StorageType::SSD { .. }));
+    }
+
+    #[test]
+    fn test_md_fixture() {
+        let device = detect_block_device_by_devnum(&fixture("md"), makedev(9, 0)).unwrap();
+
+        assert_eq!(
+            device.kind,
+            DeviceKind::Raid {
+                level: Some("raid1".to_string())
+            }
+        );
+        let members: Vec<_> = device
+            .underlying
+            .iter()
+            .map(|member| member.partition.as_deref().unwrap())
+            .collect();
+        assert_eq!(members, ["sdb1", "sdc1"]);
+        // One SSD and one HDD member: the HDD decides
+        assert!(matches!(device.storage_type, StorageType::HDD { .. }));
+    }
+
+    #[test]
+    fn test_loop_fixture() {
+        let device = detect_block_device_by_devnum(&fixture("loop"), makedev(7, 0)).unwrap();
+
+        assert_eq!(
+            device.kind,
+            DeviceKind::Loop {
+                backing_file: Some(PathBuf::from("/var/lib/machines/image.raw"))
+            }
+        );
+        // The backing file is not part of the fixture, so nothing is known
+        // about the storage underneath
+        assert!(device.underlying.is_empty());
+        assert!(matches!(device.storage_type, StorageType::Unknown));
+    }
+
+    #[test]
+    fn test_missing_device() {
+        assert!(detect_block_device_by_devnum(&fixture("nvme"), makedev(0, 22)).is_none());
+    }
 }
@@ -1,10 +1,40 @@
 pub mod detector;
+pub mod swap;
 
 #[derive(Debug, Clone)]
 pub enum StorageType {
-    HDD { rotational: bool, block_size: usize },
-    SSD { trim_support: bool },
-    NVMe { optimal_io_size: usize },
+    HDD {
+        rotational: bool,
+        block_size: usize,
+    },
+    SSD {
+        trim_support: bool,
+    },
+    NVMe {
+        optimal_io_size: usize,
+    },
+    /// USB sticks, SD/MMC cards and similar hot-pluggable media. Their
+    /// controllers remap writes like a cheap SSD even when sysfs claims the
+    /// device is rotational.
+    Removable {
+        transport: Transport,
+        trim_support: bool,
+    },
+    /// tmpfs and ramfs: the data is in RAM (and, for tmpfs, maybe swap),
+    /// and an overwrite replaces the page itself
+    Memory {
+        swappable: bool,
+    },
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Sata,
+    Nvme,
+    Usb,
+    Mmc,
+    Virtio,
     Unknown,
 }
 
@@ -16,6 +46,8 @@ impl StorageType {
             StorageType::NVMe {
                 optimal_io_size, ..
             } => *optimal_io_size,
+            StorageType::Removable { .. } => 4096,
+            StorageType::Memory { .. } => 4096,
             StorageType::Unknown => 4096,
         }
     }
@@ -25,6 +57,8 @@ impl StorageType {
             StorageType::HDD { .. } => 3,
             StorageType::SSD { .. } => 1,
             StorageType::NVMe { .. } => 1,
+            StorageType::Removable { .. } => 1,
+            StorageType::Memory { .. } => 1,
             StorageType::Unknown => 3,
         }
     }
@@ -32,4 +66,8 @@ impl StorageType {
     pub fn supports_secure_erase(&self) -> bool {
         matches!(self, StorageType::SSD { .. } | StorageType::NVMe { .. })
     }
+
+    pub fn is_removable(&self) -> bool {
+        matches!(self, StorageType::Removable { .. })
+    }
 }
@@ -0,0 +1,93 @@
+use super::detector::{detect_block_device_by_devnum, detect_block_device_by_name};
+use crate::sysroot::SysRoot;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// One active swap area from `/proc/swaps`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapArea {
+    pub name: String,
+    pub is_file: bool,
+    /// On dm-crypt, or in RAM (zram) where nothing persists
+    pub protected: bool,
+}
+
+pub fn read_swaps(root: &SysRoot) -> Vec<SwapArea> {
+    let Ok(content) = fs::read_to_string(root.proc("swaps")) else {
+        return Vec::new();
+    };
+
+    content
+        .lines()
+        .skip(1) // Header: Filename Type Size Used Priority
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = unescape_spaces(fields.next()?);
+            let is_file = fields.next()? == "file";
+            let protected = is_protected(root, &name, is_file);
+            Some(SwapArea {
+                name,
+                is_file,
+                protected,
+            })
+        })
+        .collect()
+}
+
+/// Active swap areas where swapped-out pages would persist in clear text
+pub fn unencrypted_swap(root: &SysRoot) -> Vec<SwapArea> {
+    read_swaps(root)
+        .into_iter()
+        .filter(|area| !area.protected)
+        .collect()
+}
+
+fn is_protected(root: &SysRoot, name: &str, is_file: bool) -> bool {
+    let device = if is_file {
+        fs::metadata(root.path(name))
+            .ok()
+            .and_then(|metadata| detect_block_device_by_devnum(root, metadata.dev()))
+    } else {
+        // Resolve /dev/mapper/* links to the dm-N kernel name
+        let path = fs::canonicalize(root.path(name)).unwrap_or_else(|_| root.path(name));
+        let kernel_name = Path::new(&path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if kernel_name.starts_with("zram") {
+            return true;
+        }
+        detect_block_device_by_name(root, &kernel_name)
+    };
+
+    device.is_some_and(|device| device.is_encrypted())
+}
+
+/// /proc/swaps escapes spaces in paths as `\040`
+fn unescape_spaces(name: &str) -> String {
+    name.replace("\\040", " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swap_encryption() {
+        let root =
+            SysRoot::new(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/dm-crypt"));
+        let swaps = read_swaps(&root);
+
+        assert_eq!(swaps.len(), 3);
+        assert_eq!(swaps[0].name, "/dev/dm-0");
+        assert!(swaps[0].protected);
+        assert_eq!(swaps[1].name, "/dev/sda1");
+        assert!(!swaps[1].protected);
+        assert!(swaps[2].protected); // zram
+
+        let exposed = unencrypted_swap(&root);
+        assert_eq!(exposed.len(), 1);
+        assert_eq!(exposed[0].name, "/dev/sda1");
+    }
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Root directory that sysfs and procfs are read from. Detection code goes
+/// through this instead of hard-coding `/sys` and `/proc` so it can be
+/// pointed at a fixture tree describing a machine we don't have.
+#[derive(Debug, Clone)]
+pub struct SysRoot {
+    root: PathBuf,
+}
+
+impl SysRoot {
+    /// The running system
+    pub fn host() -> Self {
+        Self {
+            root: PathBuf::from("/"),
+        }
+    }
+
+    /// A directory laid out like `/`, containing at least `sys/` and `proc/`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        // Canonicalize so paths resolved through sysfs symlinks still
+        // compare equal to paths built from the root
+        let root = fs::canonicalize(&root).unwrap_or(root);
+        Self { root }
+    }
+
+    pub fn sys(&self, relative: impl AsRef<Path>) -> PathBuf {
+        self.root.join("sys").join(relative)
+    }
+
+    pub fn proc(&self, relative: impl AsRef<Path>) -> PathBuf {
+        self.root.join("proc").join(relative)
+    }
+
+    /// Map an absolute path as seen by the described system (for example a
+    /// loop device's backing file) into this root.
+    pub fn path(&self, absolute: impl AsRef<Path>) -> PathBuf {
+        let absolute = absolute.as_ref();
+        self.root
+            .join(absolute.strip_prefix("/").unwrap_or(absolute))
+    }
+}
+
+impl Default for SysRoot {
+    fn default() -> Self {
+        Self::host()
+    }
+}
+
+/// Read a sysfs/procfs attribute, trimmed; empty attributes read as `None`
+pub fn read_string(path: impl AsRef<Path>) -> Option<String> {
+    let value = fs::read_to_string(path).ok()?;
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+pub fn read_value(path: impl AsRef<Path>) -> Option<usize> {
+    read_string(path)?.parse().ok()
+}